@@ -9,14 +9,14 @@ extern crate proc_macro;
 use core::mem;
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::quote;
+use quote::{format_ident, quote, quote_spanned};
 use std::borrow::Cow;
 use syn::{
     parse::{self, Parse, ParseStream},
     parse_macro_input,
     punctuated::Punctuated,
     spanned::Spanned,
-    Expr, LitStr, Token,
+    Expr, ExprLit, Lit, LitStr, Token,
 };
 
 #[proc_macro]
@@ -61,22 +61,63 @@ pub fn ceprintln(input: TokenStream) -> TokenStream {
 
 #[proc_macro]
 pub fn csprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, true, false)
+    csnprintf(input, Output::Str, false, false)
 }
 
 #[proc_macro]
 pub fn sprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, true, false)
+    csnprintf(input, Output::Str, false, false)
 }
 
 #[proc_macro]
 pub fn cbprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, false, false)
+    csnprintf(input, Output::Bytes, false, false)
 }
 
 #[proc_macro]
 pub fn bprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, false, false)
+    csnprintf(input, Output::Bytes, false, false)
+}
+
+#[proc_macro]
+pub fn sprint_str(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::StrSlice, false, false)
+}
+
+#[proc_macro]
+pub fn bprint_slice(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::BytesSlice, false, false)
+}
+
+#[proc_macro]
+pub fn write(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::Cursor, false, false)
+}
+
+#[proc_macro]
+pub fn writeln(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::Cursor, true, false)
+}
+
+#[proc_macro]
+pub fn format_array(input: TokenStream) -> TokenStream {
+    array_string(input, false)
+}
+
+// 与`std::format!`同名会导致本crate中无法使用`format!`, 由hifmt重命名导出
+#[proc_macro]
+pub fn format_string(input: TokenStream) -> TokenStream {
+    alloc_format(input, Output::String, false)
+}
+
+#[proc_macro]
+pub fn format_bytes(input: TokenStream) -> TokenStream {
+    alloc_format(input, Output::Vec, false)
+}
+
+#[proc_macro]
+pub fn args(input: TokenStream) -> TokenStream {
+    cargs(input, false)
 }
 
 #[proc_macro]
@@ -121,144 +162,478 @@ pub fn nolibc_ceprintln(input: TokenStream) -> TokenStream {
 
 #[proc_macro]
 pub fn nolibc_csprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, true, true)
+    csnprintf(input, Output::Str, false, true)
 }
 
 #[proc_macro]
 pub fn nolibc_sprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, true, true)
+    csnprintf(input, Output::Str, false, true)
 }
 
 #[proc_macro]
 pub fn nolibc_cbprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, false, true)
+    csnprintf(input, Output::Bytes, false, true)
 }
 
 #[proc_macro]
 pub fn nolibc_bprint(input: TokenStream) -> TokenStream {
-    csnprintf(input, false, true)
+    csnprintf(input, Output::Bytes, false, true)
+}
+
+#[proc_macro]
+pub fn nolibc_sprint_str(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::StrSlice, false, true)
+}
+
+#[proc_macro]
+pub fn nolibc_bprint_slice(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::BytesSlice, false, true)
+}
+
+#[proc_macro]
+pub fn nolibc_write(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::Cursor, false, true)
 }
 
-fn csnprintf(input: TokenStream, is_str: bool, nolibc: bool) -> TokenStream {
+#[proc_macro]
+pub fn nolibc_writeln(input: TokenStream) -> TokenStream {
+    csnprintf(input, Output::Cursor, true, true)
+}
+
+#[proc_macro]
+pub fn nolibc_format_array(input: TokenStream) -> TokenStream {
+    array_string(input, true)
+}
+
+#[proc_macro]
+pub fn nolibc_format_string(input: TokenStream) -> TokenStream {
+    alloc_format(input, Output::String, true)
+}
+
+#[proc_macro]
+pub fn nolibc_format_bytes(input: TokenStream) -> TokenStream {
+    alloc_format(input, Output::Vec, true)
+}
+
+#[proc_macro]
+pub fn nolibc_args(input: TokenStream) -> TokenStream {
+    cargs(input, true)
+}
+
+// 创建`::hifmt::ArrayString`并通过`write!`输出, 没有指定长度时由返回值的类型推导
+fn array_string(input: TokenStream, nolibc: bool) -> TokenStream {
+    let ArrayInput { len, rest } = parse_macro_input!(input as ArrayInput);
+    let ty = match len {
+        None => quote!(::hifmt::ArrayString),
+        Some(len @ (Expr::Lit(_) | Expr::Path(_))) => quote!(::hifmt::ArrayString::<#len>),
+        Some(len) => quote!(::hifmt::ArrayString::<{ #len }>),
+    };
+    let write: proc_macro2::TokenStream = csnprintf(
+        quote!(_hifmt_array, #rest).into(),
+        Output::Cursor,
+        false,
+        nolibc,
+    )
+    .into();
+    (quote! {{
+        let mut _hifmt_array = #ty::new();
+        #write;
+        _hifmt_array
+    }})
+    .into()
+}
+
+// `format!`/`format_bytes!`没有buf参数, 补一个不会用到的占位符之后按`csnprintf`解析
+fn alloc_format(input: TokenStream, output: Output, nolibc: bool) -> TokenStream {
+    let input = proc_macro2::TokenStream::from(input);
+    csnprintf(quote!(_hifmt_alloc, #input).into(), output, false, nolibc)
+}
+
+// `args!`: 参数在这里求值一次, 由`match`绑定使临时值存活到语句结束(同`format_args!`),
+// 转换和输出放在闭包中, 由`::hifmt::Args`在调用`write_to`或者转发时执行
+fn cargs(input: TokenStream, nolibc: bool) -> TokenStream {
+    let input = parse_macro_input!(input as Input);
+    let format = input.format.value();
+    let span = input.format.span();
+    cformat(&format, &input, nolibc, |vars, args, pieces, lowered| {
+        let sources = match resolve(pieces, &input) {
+            Err(e) => return e.to_compile_error().into(),
+            Ok((sources, _)) => sources,
+        };
+        let exprs = sources.iter().map(|(expr, _)| expr);
+        let idents: Vec<_> = (sources.iter().enumerate())
+            .map(|(idx, (_, span))| arg_ident(idx + 1, *span))
+            .collect();
+        // 前面是参数本身的绑定, 已经由`match`代替
+        let vars = &vars[sources.len()..];
+        let write = if !nolibc {
+            let ident = hifmt_ident(0, span);
+            let buf_args = buf_pre_args(&ident);
+            let mut calls = vec![];
+            let fd = [(quote!(_hifmt_fd), quote!(i32))];
+            for (ty, pre, out) in [
+                (quote!(DprintfFn), &fd[..], quote!(Fd)),
+                (quote!(SnprintfFn), &buf_args[..], quote!(Buf)),
+            ] {
+                let callee = Callee {
+                    func: quote!(_hifmt_f),
+                    ty: Some(quote!(::hifmt::arg::#ty)),
+                };
+                match libc_call(&callee, pre, out, pieces, args, lowered, span) {
+                    Err(e) => return e.to_compile_error().into(),
+                    Ok(call) => calls.push(call),
+                }
+            }
+            let (fd_call, buf_call) = (&calls[0], &calls[1]);
+            quote! {
+                move |_hifmt_sink: ::hifmt::arg::Sink<'_>| -> i32 {
+                    #(#vars)*
+                    match _hifmt_sink {
+                        ::hifmt::arg::Sink::Fd(_hifmt_f, _hifmt_fd) => #fd_call,
+                        ::hifmt::arg::Sink::Buf(_hifmt_f, #ident) => #buf_call,
+                    }
+                }
+            }
+        } else {
+            let call: proc_macro2::TokenStream = nolibc_cformat(
+                span,
+                quote! {#(#vars)*},
+                pieces,
+                lowered,
+                |formatter| quote!(let #formatter = _hifmt_dyn;),
+            )
+            .into();
+            quote! {
+                move |_hifmt_dyn: &mut dyn ::hifmt::Formatter| -> usize {
+                    let _hifmt_size = #call;
+                    _hifmt_size as usize
+                }
+            }
+        };
+        (quote! {
+            ::hifmt::Args::new(&match (#(&(#exprs),)*) {
+                (#(#idents,)*) => #write,
+            })
+        })
+        .into()
+    })
+}
+
+// 输出到buf的宏的输出目标
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Bytes,
+    Str,
+    // `bprint_slice!`/`sprint_str!`返回写入的`&[u8]`/`&str`以及是否截断
+    BytesSlice,
+    StrSlice,
+    // `write!`追加到`::hifmt::Cursor`或者`::hifmt::ArrayString`已经写入的内容之后,
+    // nolibc模式下可以输出到任意`Formatter`
+    Cursor,
+    // `format_bytes!`/`format!`先计算长度, 再输出到一次分配的`Vec<u8>`/`String`
+    Vec,
+    String,
+}
+
+fn csnprintf(input: TokenStream, output: Output, ln: bool, nolibc: bool) -> TokenStream {
     let input = parse_macro_input!(input as BufInput);
     let mut buf_format = input.input.format.value();
-    buf_format.push('\0');
+    if ln {
+        buf_format.push('\n');
+    }
 
     let buf = &input.buf;
     let ident = hifmt_ident(0, buf.span());
     let mut buf_vars = vec![];
-    let mut buf_args = vec![];
-    if is_str {
-        buf_vars.push(quote!(let #ident: &mut str = #buf;));
-        buf_args.push(quote!(#ident.as_bytes_mut().as_mut_ptr()));
-    } else {
-        buf_vars.push(quote!(let #ident: &mut [u8] = #buf;));
-        buf_args.push(quote!(#ident.as_mut_ptr()));
+    match output {
+        Output::Str | Output::StrSlice => buf_vars.push(quote!(let #ident: &mut str = #buf;)),
+        Output::Bytes | Output::BytesSlice => buf_vars.push(quote!(let #ident: &mut [u8] = #buf;)),
+        Output::Cursor => {
+            // libc模式下snprintf输出到`buf[pos..]`
+            if !nolibc {
+                buf_vars.push(
+                    quote!(let #ident: &mut [u8] = ::hifmt::arg::Append::spare(_hifmt_cursor);),
+                );
+            }
+        }
+        Output::Vec | Output::String => {}
     }
-    buf_args.push(quote!(#ident.len() as usize));
+    let alloc = matches!(output, Output::Vec | Output::String);
+    let buf_args = buf_pre_args(&ident);
     cformat(
         &buf_format,
         &input.input,
-        |test_vars, vars, args, format, pieces, idents| {
-            if !nolibc {
-                (quote!{{ #(#test_vars)* #(#buf_vars)* #(#vars)* unsafe { snprintf( #(#buf_args),*, #format.as_bytes().as_ptr(), #(#args),*) }}}).into()
+        nolibc,
+        |vars, args, pieces, lowered| {
+            // 计算长度和输出两次调用共用参数, 参数只求值一次
+            let pre_tokens = if alloc {
+                quote!()
+            } else {
+                quote! {#(#vars)*}
+            };
+            let call = if !nolibc {
+                let span = input.input.format.span();
+                let call = libc_call(
+                    &Callee::name(quote!(snprintf)),
+                    &buf_args,
+                    quote!(Buf),
+                    pieces,
+                    args,
+                    lowered,
+                    span,
+                );
+                let call = match call {
+                    Err(e) => return e.to_compile_error().into(),
+                    Ok(call) => call,
+                };
+                quote! {{ #pre_tokens #call }}
             } else {
                 nolibc_cformat(
                     input.input.format.span(),
-                    quote! {#(#test_vars)* #(#buf_vars)* #(#vars)*},
+                    pre_tokens,
                     pieces,
-                    idents,
+                    lowered,
                     |formatter| {
+                        // `&mut str`写入之后由`utf8_fixup`恢复为合法的UTF-8
+                        let buf = match output {
+                            Output::Cursor => return quote!(let #formatter = _hifmt_cursor;),
+                            Output::Str | Output::StrSlice => {
+                                quote!(unsafe { #ident.as_bytes_mut() })
+                            }
+                            Output::Bytes | Output::BytesSlice | Output::Vec | Output::String => {
+                                quote!(#ident)
+                            }
+                        };
                         quote! {
-                            let mut #formatter = unsafe { ::hifmt::BufFormatter::new(#(#buf_args),*) };
+                            let #formatter = &mut ::hifmt::BufFormatter::from_slice(#buf);
                         }
                     },
                 )
+                .into()
+            };
+            if alloc {
+                return alloc_call(&ident, vars, call, output, nolibc, |formatter| {
+                    nolibc_cformat(
+                        input.input.format.span(),
+                        quote!(),
+                        pieces,
+                        lowered,
+                        |f| quote!(let #f = #formatter;),
+                    )
+                    .into()
+                });
+            }
+            // `&mut str`需要保持合法的UTF-8, 返回写入的字节数以及是否截断
+            let ret = match output {
+                Output::Str => quote!(::hifmt::arg::utf8_fixup(#ident, _hifmt_ret)),
+                Output::StrSlice => quote!(::hifmt::arg::str_written(#ident, _hifmt_ret)),
+                Output::BytesSlice => quote!(::hifmt::arg::bytes_written(#ident, _hifmt_ret)),
+                Output::Cursor if !nolibc => {
+                    quote!(::hifmt::arg::Append::advance(_hifmt_cursor, _hifmt_ret))
+                }
+                Output::Bytes | Output::Cursor | Output::Vec | Output::String => {
+                    quote!(_hifmt_ret)
+                }
+            };
+            let tokens = quote! {{
+                #(#buf_vars)*
+                let _hifmt_ret = #call;
+                #ret
+            }};
+            if output != Output::Cursor {
+                return tokens.into();
             }
+            // 参数可以是值或者`&mut`引用, 由方法调用自动解引用;
+            // `match`使`&mut Uart::new()`这样的临时值存活到输出结束
+            (quote! {{
+                use ::hifmt::arg::WriteTarget as _;
+                match (#buf).write_target() {
+                    _hifmt_cursor => #tokens,
+                }
+            }})
+            .into()
         },
     )
 }
 
+// snprintf输出到`ident`时的前置参数及其类型
+fn buf_pre_args(ident: &syn::Ident) -> [(proc_macro2::TokenStream, proc_macro2::TokenStream); 2] {
+    [
+        (quote!(#ident.as_mut_ptr()), quote!(*mut u8)),
+        (quote!(#ident.len() as usize), quote!(usize)),
+    ]
+}
+
+// `format!`/`format_bytes!`: 先输出到空的buf得到完整的长度, 再按长度分配一次并输出.
+// libc模式下snprintf输出到分配的buf, 最后去掉末尾的'\0'; nolibc模式下直接由`Vec<u8>`实现的`Formatter`追加
+fn alloc_call<F>(
+    ident: &syn::Ident,
+    vars: &[proc_macro2::TokenStream],
+    call: proc_macro2::TokenStream,
+    output: Output,
+    nolibc: bool,
+    nolibc_fill: F,
+) -> TokenStream
+where
+    F: FnOnce(proc_macro2::TokenStream) -> proc_macro2::TokenStream,
+{
+    let fill = if nolibc {
+        nolibc_fill(quote!(&mut _hifmt_vec))
+    } else {
+        quote! {{
+            let #ident: &mut [u8] = &mut _hifmt_vec;
+            #call
+        }}
+    };
+    let ret = match output {
+        Output::String => quote!(::hifmt::arg::into_string(_hifmt_vec, _hifmt_len)),
+        _ => quote!(::hifmt::arg::into_bytes(_hifmt_vec, _hifmt_len)),
+    };
+    (quote! {{
+        #(#vars)*
+        let _hifmt_len = {
+            let #ident: &mut [u8] = &mut [];
+            #call
+        };
+        let mut _hifmt_vec = ::hifmt::arg::alloc_buf(_hifmt_len);
+        #fill;
+        #ret
+    }})
+    .into()
+}
+
 fn cprintf(input: TokenStream, ln: bool, fd: i32, nolibc: bool) -> TokenStream {
     let input = parse_macro_input!(input as Input);
     let mut format = input.format.value();
 
     if ln {
-        format.push_str("\n\0");
-    } else {
-        format.push('\0');
+        format.push('\n');
     }
-    cformat(
-        &format,
-        &input,
-        |test_vars, vars, args, format, pieces, idents| {
-            if !nolibc {
-                (quote! { { #(#test_vars)* #(#vars)* unsafe { dprintf( #fd, #format.as_bytes().as_ptr(), #(#args),*) } } }).into()
-            } else {
-                nolibc_cformat(
-                    input.format.span(),
-                    quote! {#(#test_vars)* #(#vars)*},
-                    pieces,
-                    idents,
-                    |formatter| {
-                        quote! {let mut #formatter = _hifmt_Formatter::new(#fd);}
-                    },
-                )
-            }
-        },
-    )
+    cformat(&format, &input, nolibc, |vars, args, pieces, lowered| {
+        if !nolibc {
+            let pre = [(quote!(#fd), quote!(i32))];
+            let span = input.format.span();
+            let call = match libc_call(
+                &Callee::name(quote!(dprintf)),
+                &pre,
+                quote!(Fd),
+                pieces,
+                args,
+                lowered,
+                span,
+            ) {
+                Err(e) => return e.to_compile_error().into(),
+                Ok(call) => call,
+            };
+            (quote! {{ #(#vars)* #call }}).into()
+        } else {
+            // 通过链接时注册的全局formatter输出, 任意模块和crate中都可以使用
+            let call: proc_macro2::TokenStream = nolibc_cformat(
+                input.format.span(),
+                quote!(),
+                pieces,
+                lowered,
+                |formatter| {
+                    quote! {let #formatter = _hifmt_global;}
+                },
+            )
+            .into();
+            (quote! {{
+                #(#vars)*
+                ::hifmt::arg::print(#fd, &mut |_hifmt_global: &mut dyn ::hifmt::Formatter| #call)
+            }})
+            .into()
+        }
+    })
 }
 
 fn nolibc_cformat<F>(
     span: Span,
     pre_tokens: proc_macro2::TokenStream,
     pieces: &[Piece],
-    idents: &[syn::Ident],
+    lowered: &[Lowered],
     make_formatter: F,
 ) -> TokenStream
 where
     F: FnOnce(&syn::Ident) -> proc_macro2::TokenStream,
 {
-    let formatter = hifmt_ident(pieces.len() + 1, span);
-    let size = hifmt_ident(pieces.len() + 2, span);
+    let formatter = syn::Ident::new("_hifmt_formatter", span);
+    let size = syn::Ident::new("_hifmt_size", span);
     let mut tokens = vec![];
     tokens.push(pre_tokens);
     tokens.push(make_formatter(&formatter));
     tokens.push(quote! { let mut #size = 0_usize; });
     tokens.push(quote! { use ::hifmt::Formatter; });
 
-    let mut ident_iter = idents.iter();
+    let mut lowered = lowered.iter();
     for piece in pieces {
-        if let Piece::Literal(literal) = piece {
-            let literal = if !literal.as_bytes().ends_with(b"\0") {
-                literal
-            } else {
-                &literal[..literal.len() - 1]
-            };
-            if !literal.is_empty() {
+        let arg = match piece {
+            Piece::Literal(literal) => {
+                let literal: &str = literal;
                 tokens.push(quote! { #size += #formatter.write_buf(#literal.as_bytes()); });
+                continue;
             }
-            continue;
-        }
-        let ident = ident_iter.next().unwrap();
-        match piece {
-            Piece::Str => tokens.push(quote! { #size += #formatter.write_buf(#ident.as_bytes()); }),
-            Piece::Bytes | Piece::Char => {
-                tokens.push(quote! { #size += #formatter.write_buf(#ident); })
-            }
-            Piece::Pointer => tokens.push(quote! { #size += #formatter.write_ptr(#ident); }),
-            Piece::CStr => {
-                tokens.push(quote! { #size += unsafe { #formatter.write_cstr(#ident) }; })
+            Piece::Arg(arg) => arg,
+        };
+        let Lowered {
+            ident,
+            width,
+            precision,
+            ..
+        } = lowered.next().unwrap();
+        let write = if arg.spec.is_plain() {
+            match arg.kind {
+                Kind::Str | Kind::Bytes | Kind::Char => quote! { #formatter.write_buf(#ident) },
+                Kind::Pointer => quote! { #formatter.write_ptr(#ident) },
+                Kind::CStr => quote! {
+                    unsafe { #formatter.write_cstr_spec(#ident.0, &::hifmt::Spec::DEFAULT.with_max(#ident.1)) }
+                },
+                Kind::Exp if cfg!(feature = "f32") => quote! { #formatter.write_f32(#ident) },
+                Kind::Exp => quote! { #formatter.write_f64(#ident) },
+                Kind::Fixed | Kind::General | Kind::HexFloat => {
+                    let method = float_method(arg.kind, span);
+                    quote! { #formatter.#method(#ident, &::hifmt::Spec::DEFAULT) }
+                }
+                Kind::Signed | Kind::Unsigned => {
+                    quote! { ::hifmt::arg::CInt::write(#ident, #formatter) }
+                }
+                Kind::Hex => quote! { #formatter.write_hex(#ident) },
+                Kind::UpperHex => quote! { #formatter.write_upper_hex(#ident) },
+                Kind::Oct => quote! { #formatter.write_oct(#ident) },
+                Kind::Bin => quote! { #formatter.write_bin(#ident) },
+                Kind::Signed128 => quote! { #formatter.write_i128(#ident) },
+                Kind::Unsigned128 => quote! { #formatter.write_u128(#ident) },
+                Kind::Hex128 => quote! { #formatter.write_hex128(#ident) },
+                Kind::CChar => quote! { #formatter.write_buf(&[#ident as u8]) },
             }
-            Piece::Double => tokens.push(quote! { #size += #formatter.write_f64(#ident); }),
-            Piece::Signed => tokens.push(quote! { #size += #formatter.write_i64(#ident); }),
-            Piece::Unsigned => {
-                tokens.push(quote! { #size += #formatter.write_u64(#ident as u64); })
+        } else {
+            let spec = nolibc_spec(&arg.spec, width, precision);
+            match arg.kind {
+                Kind::Str | Kind::Bytes | Kind::Char => {
+                    quote! { #formatter.write_buf_spec(#ident, &#spec) }
+                }
+                Kind::Pointer => quote! { #formatter.write_ptr_spec(#ident, &#spec) },
+                Kind::CStr => quote! {
+                    unsafe { #formatter.write_cstr_spec(#ident.0, &#spec.with_max(#ident.1)) }
+                },
+                Kind::Exp | Kind::Fixed | Kind::General | Kind::HexFloat => {
+                    let method = float_method(arg.kind, span);
+                    quote! { #formatter.#method(#ident, &#spec) }
+                }
+                Kind::Signed | Kind::Unsigned => {
+                    quote! { ::hifmt::arg::CInt::write_spec(#ident, #formatter, &#spec) }
+                }
+                Kind::Hex => quote! { #formatter.write_hex_spec(#ident, &#spec) },
+                Kind::UpperHex => quote! { #formatter.write_upper_hex_spec(#ident, &#spec) },
+                Kind::Oct => quote! { #formatter.write_oct_spec(#ident, &#spec) },
+                Kind::Bin => quote! { #formatter.write_bin_spec(#ident, &#spec) },
+                Kind::Signed128 => quote! { #formatter.write_i128_spec(#ident, &#spec) },
+                Kind::Unsigned128 => quote! { #formatter.write_u128_spec(#ident, &#spec) },
+                Kind::Hex128 => quote! { #formatter.write_hex128_spec(#ident, &#spec) },
+                Kind::CChar => quote! { #formatter.write_buf_spec(&[#ident as u8], &#spec) },
             }
-            Piece::Hex => tokens.push(quote! { #size += #formatter.write_hex(#ident as u64); }),
-            Piece::CChar => tokens.push(quote! { #size += #formatter.write_buf(&[#ident as u8]); }),
-            _ => unreachable!(),
-        }
+        };
+        tokens.push(quote! { #size += #write; });
     }
     (quote! {{
         #(#tokens)*
@@ -267,125 +642,747 @@ where
     .into()
 }
 
+/// feature = "f32"时浮点参数转换为f32, 使用`Formatter::write_f32_***`系列接口
+fn float_method(kind: Kind, span: Span) -> syn::Ident {
+    let ty = if cfg!(feature = "f32") { "f32" } else { "f64" };
+    let style = match kind {
+        Kind::Exp => "exp",
+        Kind::Fixed => "fixed",
+        Kind::General => "general",
+        _ => "hex",
+    };
+    syn::Ident::new(&format!("write_{}_{}", ty, style), span)
+}
+
+fn nolibc_spec(
+    spec: &Spec,
+    width: &Option<syn::Ident>,
+    precision: &Option<syn::Ident>,
+) -> proc_macro2::TokenStream {
+    let fill = spec.fill.unwrap_or(' ');
+    let align = match spec.align {
+        Some('<') => quote!(Left),
+        Some('^') => quote!(Center),
+        _ => quote!(Right),
+    };
+    let (sign, alternate, zero) = (spec.sign, spec.alternate, spec.zero);
+    let fixed_width = match spec.width {
+        Count::Is(n) => n,
+        _ => 0,
+    };
+    let fixed_precision = match spec.precision {
+        Count::Is(n) => quote!(Some(#n)),
+        _ => quote!(None),
+    };
+    let mut tokens = quote! {
+        ::hifmt::Spec {
+            fill: #fill,
+            align: ::hifmt::Align::#align,
+            sign: #sign,
+            alternate: #alternate,
+            zero: #zero,
+            width: #fixed_width,
+            precision: #fixed_precision,
+        }
+    };
+    if let Some(width) = width {
+        tokens = quote!((#tokens.with_width(#width)));
+    }
+    if let Some(precision) = precision {
+        tokens = quote!((#tokens.with_precision(#precision)));
+    }
+    tokens
+}
+
 fn hifmt_ident(idx: usize, span: Span) -> syn::Ident {
     let name = format!("_hifmt_{}", idx);
     syn::Ident::new(&name, span)
 }
 
-fn cformat<F>(format: &str, input: &Input, f: F) -> TokenStream
+fn arg_ident(idx: usize, span: Span) -> syn::Ident {
+    let name = format!("_hifmt_arg_{}", idx);
+    syn::Ident::new(&name, span)
+}
+
+// 参数转换后的变量: 参数本身, 以及`*`指定的宽度和精度;
+// args为libc模式下截止到该参数为止的printf参数个数
+struct Lowered {
+    ident: syn::Ident,
+    width: Option<syn::Ident>,
+    precision: Option<syn::Ident>,
+    args: usize,
+}
+
+// 格式说明引用的参数下标: `*`宽度, `*`精度, 参数值
+struct Refs {
+    width: Option<usize>,
+    precision: Option<usize>,
+    value: usize,
+}
+
+// 参数表达式及其span: 依次为位置参数, 命名参数`name = expr`, 格式串中直接引用的变量
+type Sources = Vec<(proc_macro2::TokenStream, Span)>;
+
+/// `{:cs}`的字节串字面量必须以`\0`结尾, 否则C字符串没有结束符
+fn check_cstr(mut expr: &Expr) -> parse::Result<()> {
+    loop {
+        expr = match expr {
+            Expr::Reference(r) => &r.expr,
+            Expr::Paren(p) => &p.expr,
+            Expr::Group(g) => &g.expr,
+            Expr::Lit(ExprLit {
+                lit: Lit::ByteStr(s),
+                ..
+            }) => {
+                let value = s.value();
+                if value.last() != Some(&0) {
+                    return Err(parse::Error::new(
+                        s.span(),
+                        "{:cs} requires a byte string literal ending with `\\0`",
+                    ));
+                }
+                // 与`CStr::from_bytes_with_nul`一致, 中间的`\0`会截断输出
+                if value[..value.len() - 1].contains(&0) {
+                    return Err(parse::Error::new(
+                        s.span(),
+                        "{:cs} byte string literal must not contain an interior `\\0`",
+                    ));
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn resolve(pieces: &[Piece], input: &Input) -> parse::Result<(Sources, Vec<Refs>)> {
+    let span = input.format.span();
+    let mut sources = vec![];
+    let mut names = vec![];
+    for arg in input.args.iter() {
+        if let Expr::Assign(assign) = arg {
+            let name = match &*assign.left {
+                Expr::Path(path) if path.path.get_ident().is_some() => {
+                    path.path.get_ident().unwrap().to_string()
+                }
+                left => return Err(parse::Error::new(left.span(), "invalid argument name")),
+            };
+            if names.contains(&name) {
+                return Err(parse::Error::new(
+                    assign.left.span(),
+                    format!("duplicate argument named `{}`", name),
+                ));
+            }
+            names.push(name);
+            let expr = &assign.right;
+            sources.push((quote!(#expr), expr.span()));
+        } else if !names.is_empty() {
+            return Err(parse::Error::new(
+                arg.span(),
+                "positional arguments cannot follow named arguments",
+            ));
+        } else {
+            sources.push((quote!(#arg), arg.span()));
+        }
+    }
+    let positional = sources.len() - names.len();
+    let explicit = sources.len();
+    let exprs: Vec<_> = input
+        .args
+        .iter()
+        .map(|arg| match arg {
+            Expr::Assign(assign) => &*assign.right,
+            arg => arg,
+        })
+        .collect();
+
+    let mut next = 0;
+    let mut required = 0;
+    let mut captured = vec![];
+    let mut refs = vec![];
+    for piece in pieces {
+        let arg = match piece {
+            Piece::Literal(_) => continue,
+            Piece::Arg(arg) => arg,
+        };
+        let mut position = |idx: usize| {
+            required = required.max(idx + 1);
+            idx
+        };
+        let mut take_next = || {
+            next += 1;
+            position(next - 1)
+        };
+        let width = (arg.spec.width == Count::Arg).then(&mut take_next);
+        let precision = (arg.spec.precision == Count::Arg).then(&mut take_next);
+        let value = match &arg.value {
+            ArgRef::Next => take_next(),
+            ArgRef::Index(idx) => position(*idx),
+            ArgRef::Name(name) => match names.iter().position(|n| n == name) {
+                Some(idx) => positional + idx,
+                None => match captured.iter().position(|n| n == name) {
+                    Some(idx) => explicit + idx,
+                    None => {
+                        let ident: syn::Ident = syn::parse_str(name).map_err(|_| {
+                            parse::Error::new(
+                                span,
+                                format!("invalid format string: invalid argument name `{}`", name),
+                            )
+                        })?;
+                        let ident = syn::Ident::new(&ident.to_string(), span);
+                        captured.push(name.clone());
+                        sources.push((quote!(#ident), span));
+                        sources.len() - 1
+                    }
+                },
+            },
+        };
+        if matches!(arg.kind, Kind::CStr) {
+            if let Some(expr) = exprs.get(value) {
+                check_cstr(expr)?;
+            }
+        }
+        refs.push(Refs {
+            width,
+            precision,
+            value,
+        });
+    }
+
+    if required > positional {
+        return Err(parse::Error::new(
+            span,
+            format!(
+                "format string required {} arguments but {} were supplied",
+                required, positional
+            ),
+        ));
+    }
+    for (idx, (_, span)) in sources.iter().enumerate().take(explicit) {
+        let used = refs
+            .iter()
+            .any(|r| r.value == idx || r.width == Some(idx) || r.precision == Some(idx));
+        if !used {
+            return Err(parse::Error::new(*span, "argument never used"));
+        }
+    }
+    Ok((sources, refs))
+}
+
+fn cformat<F>(format: &str, input: &Input, nolibc: bool, f: F) -> TokenStream
 where
     F: Fn(
         &[proc_macro2::TokenStream],
-        &[proc_macro2::TokenStream],
-        &[proc_macro2::TokenStream],
-        &str,
+        &[(proc_macro2::TokenStream, bool)],
         &[Piece],
-        &[syn::Ident],
+        &[Lowered],
     ) -> TokenStream,
 {
     let pieces = match parse(format, input.format.span()) {
         Err(e) => return e.to_compile_error().into(),
         Ok(pieces) => pieces,
     };
+    let (sources, refs) = match resolve(&pieces, input) {
+        Err(e) => return e.to_compile_error().into(),
+        Ok(resolved) => resolved,
+    };
 
-    let argc: usize = input.args.len();
-    let required_argc: usize = pieces.iter().filter(|piece| !piece.is_literal()).count();
-
-    if argc != required_argc {
-        return parse::Error::new(
-            input.format.span(),
-            format!(
-                "format string required {} arguments but {} were supplied",
-                required_argc, argc
-            ),
-        )
-        .to_compile_error()
-        .into();
-    }
-
-    let literal = gen_literal(&pieces);
     let mut args = vec![];
     let mut vars = vec![];
-    let mut test_vars = vec![];
-    let mut idents = vec![];
+    let mut lowered = vec![];
 
-    let mut i: usize = 0;
-    for piece in pieces.iter() {
-        if matches!(piece, Piece::Literal(_)) {
-            continue;
+    // 每个参数只求值一次, 同一参数可被多个格式说明引用
+    for (idx, (expr, span)) in sources.iter().enumerate() {
+        let ident = arg_ident(idx + 1, *span);
+        vars.push(quote!(let #ident = &(#expr);));
+    }
+    let mut n = 0;
+    let mut lower = |idx: usize| {
+        let span = sources[idx].1;
+        n += 1;
+        (hifmt_ident(n, span), arg_ident(idx + 1, span), span)
+    };
+    let args_iter = pieces.iter().filter_map(|piece| match piece {
+        Piece::Literal(_) => None,
+        Piece::Arg(arg) => Some(arg),
+    });
+    for (piece, refs) in args_iter.zip(refs.iter()) {
+        let mut width = None;
+        if let Some(idx) = refs.width {
+            let (ident, arg, span) = lower(idx);
+            vars.push(quote_spanned!(span=>
+                let #ident = ::hifmt::arg::Count::count(::hifmt::HiArg::hi_arg(#arg));
+            ));
+            args.push((quote!(#ident), false));
+            width = Some(ident);
+        }
+        let mut precision = None;
+        if let Some(idx) = refs.precision {
+            let (ident, arg, span) = lower(idx);
+            vars.push(quote_spanned!(span=>
+                let #ident = ::hifmt::arg::Count::count(::hifmt::HiArg::hi_arg(#arg));
+            ));
+            if !matches!(piece.kind, Kind::Str | Kind::Bytes | Kind::CStr) {
+                args.push((quote!(#ident), false));
+            }
+            precision = Some(ident);
         }
-        let arg = &input.args[i];
-        i += 1;
-        let ident = hifmt_ident(i, arg.span());
-        idents.push(ident.clone());
-        match piece {
-            Piece::Literal(_) => {}
-            Piece::Str | Piece::Bytes => {
-                args.push(quote!(#ident.len() as i32));
-                if matches!(piece, Piece::Str) {
-                    test_vars.push(quote!({ let #ident: &str = #arg; }));
-                    vars.push(quote!(let #ident: &str = #arg;));
-                    args.push(quote!(#ident.as_bytes().as_ptr()));
+        let (ident, arg, span) = lower(refs.value);
+        // 数值参数先通过`HiArg`转换为基本类型
+        let value = quote_spanned!(span=> ::hifmt::HiArg::hi_arg(#arg));
+        match piece.kind {
+            Kind::Str | Kind::Bytes => {
+                if matches!(piece.kind, Kind::Str) {
+                    vars.push(quote_spanned!(span=> let #ident: &str = *#arg;));
+                    vars.push(quote!(let #ident = #ident.as_bytes();));
                 } else {
-                    test_vars.push(quote!({ let #ident: &[u8] = #arg; }));
-                    vars.push(quote!(let #ident: &[u8] = #arg;));
-                    args.push(quote!(#ident.as_ptr()));
+                    vars.push(quote_spanned!(span=> let #ident: &[u8] = *#arg;));
+                }
+                // libc模式下先按精度截断, 含有`\0`时由`libc_call`分段输出
+                if !nolibc {
+                    let len = match (&piece.spec.precision, &precision) {
+                        (Count::Is(n), _) => Some(quote!(#ident.len().min(#n))),
+                        (_, Some(p)) => Some(quote! {
+                            if #p < 0 { #ident.len() } else { #ident.len().min(#p as usize) }
+                        }),
+                        _ => None,
+                    };
+                    if let Some(len) = len {
+                        vars.push(quote!(let #ident = &#ident[..#len];));
+                    }
+                    args.push((quote!(#ident.len().min(i32::MAX as usize) as i32), false));
+                    args.push((quote!(#ident.as_ptr()), false));
                 }
             }
-            Piece::Char => {
-                test_vars.push(quote!({ let #ident = (#arg) as i64; }));
-                vars.push(quote!(
+            Kind::Char => {
+                vars.push(quote_spanned!(span=>
                     let mut #ident = [0_u8; 4];
-                    let #ident = ::hifmt::encode_utf8(#arg, &mut #ident);
+                    let #ident = ::hifmt::encode_utf8(*#arg, &mut #ident);
                 ));
-                args.push(quote!(#ident.len() as i32));
-                args.push(quote!(#ident.as_ptr()));
+                args.push((quote!(#ident.len() as i32), false));
+                args.push((quote!(#ident.as_ptr()), false));
+            }
+            Kind::CChar => {
+                vars.push(quote_spanned!(span=> let #ident = ::hifmt::arg::CChar::c_char(#value);));
+                args.push((quote!(#ident), false));
             }
-            Piece::CChar => {
-                test_vars.push(quote!({ let #ident = (#arg) as i32; }));
-                vars.push(quote!(let #ident = (#arg) as i32;));
-                args.push(quote!(#ident));
+            Kind::CStr => {
+                vars.push(quote_spanned!(span=> let #ident = ::hifmt::arg::CStrArg::c_str(#arg);));
+                let max = match (&piece.spec.precision, &precision) {
+                    (Count::Is(n), _) => quote!(#ident.1.min(#n)),
+                    (_, Some(p)) => {
+                        quote!((if #p < 0 { #ident.1 } else { #ident.1.min(#p as usize) }))
+                    }
+                    _ => quote!(#ident.1),
+                };
+                args.push((quote!(#max.min(i32::MAX as usize) as i32), false));
+                args.push((quote!(#ident.0), false));
+            }
+            Kind::Pointer => {
+                vars.push(quote!(let #ident = *#arg as *const _ as *const u8;));
+                args.push((quote!(#ident), false));
             }
-            Piece::CStr | Piece::Pointer => {
-                test_vars.push(quote!({ let #ident = (#arg) as *const _ as *const u8; }));
-                vars.push(quote!(let #ident = (#arg) as *const _ as *const u8;));
-                args.push(quote!(#ident));
+            Kind::Exp | Kind::Fixed | Kind::General | Kind::HexFloat => {
+                // printf的变参只接受double, f32只用于nolibc
+                if nolibc && cfg!(feature = "f32") {
+                    vars.push(
+                        quote_spanned!(span=> let #ident = ::hifmt::arg::Float::f32(#value);),
+                    );
+                } else {
+                    vars.push(
+                        quote_spanned!(span=> let #ident = ::hifmt::arg::Float::f64(#value);),
+                    );
+                }
+                args.push((quote!(#ident), false));
+            }
+            Kind::Signed => {
+                vars.push(
+                    quote_spanned!(span=> let #ident = ::hifmt::arg::Signed::signed(#value);),
+                );
+                args.push((quote!(#ident), true));
+            }
+            Kind::Unsigned => {
+                vars.push(
+                    quote_spanned!(span=> let #ident = ::hifmt::arg::Unsigned::unsigned(#value);),
+                );
+                args.push((quote!(#ident), true));
+            }
+            // printf无法直接输出的整数, 符号, 前缀和补0在转换时输出, 宽度和对齐仍由printf处理
+            _ if !nolibc && is_int_str(piece) => {
+                let spec = &piece.spec;
+                let (val, neg, abs) = match piece.kind {
+                    Kind::Signed128 => (
+                        quote_spanned!(span=> ::hifmt::arg::Signed128::signed128(#value)),
+                        quote!(#ident < 0),
+                        quote!(#ident.unsigned_abs()),
+                    ),
+                    Kind::Unsigned128 => (
+                        quote_spanned!(span=> ::hifmt::arg::Unsigned128::unsigned128(#value)),
+                        quote!(false),
+                        quote!(#ident),
+                    ),
+                    Kind::Hex128 => (
+                        quote_spanned!(span=> ::hifmt::arg::Bits128::bits128(#value)),
+                        quote!(false),
+                        quote!(#ident),
+                    ),
+                    _ => (
+                        quote_spanned!(span=> ::hifmt::arg::Bits::bits(#value) as u128),
+                        quote!(false),
+                        quote!(#ident),
+                    ),
+                };
+                let buf = format_ident!("{}_buf", ident);
+                let ty = piece.kind.int_type();
+                let (sign, alternate, zero) = (spec.sign, spec.alternate, spec.zero);
+                let precision = match spec.precision {
+                    Count::Is(n) => quote!(Some(#n)),
+                    _ => quote!(None),
+                };
+                vars.push(quote_spanned!(span=>
+                    let #ident = #val;
+                    let mut #buf = [0_u8; 130];
+                    let #ident = ::hifmt::arg::IntStr {
+                        neg: #neg,
+                        val: #abs,
+                        ty: #ty,
+                        sign: #sign,
+                        alternate: #alternate,
+                        zero: #zero,
+                        precision: #precision,
+                    };
+                ));
+                if !int_zeros(spec) {
+                    vars.push(quote!(let #ident: &[u8] = #ident.write(&mut #buf).0;));
+                    args.push((quote!(#ident.len() as i32), false));
+                    args.push((quote!(#ident.as_ptr()), false));
+                } else {
+                    // 补0输出在前缀和数字之间, 宽度由`pad`换算到前缀或者数字上, `*`宽度不再直接传给printf
+                    let width = match (&spec.width, &width) {
+                        (_, Some(width)) => {
+                            args.pop();
+                            match spec.align {
+                                Some('<') => quote!(-#width.saturating_abs()),
+                                _ => quote!(#width),
+                            }
+                        }
+                        (&Count::Is(n), _) => {
+                            let n = n.min(i32::MAX as usize) as i32;
+                            match spec.align {
+                                Some('<') => quote!(-#n),
+                                _ => quote!(#n),
+                            }
+                        }
+                        _ => quote!(0),
+                    };
+                    vars.push(quote!(let #ident = #ident.pad(&mut #buf, #width);));
+                    args.push((quote!(#ident.prefix_width), false));
+                    args.push((quote!(#ident.prefix.len() as i32), false));
+                    args.push((quote!(#ident.prefix.as_ptr()), false));
+                    args.push((quote!(#ident.zeros), false));
+                    args.push((quote!(0_i32), false));
+                    args.push((quote!(#ident.digits_width), false));
+                    args.push((quote!(#ident.digits.len() as i32), false));
+                    args.push((quote!(#ident.digits.as_ptr()), false));
+                }
             }
-            Piece::Double => {
-                test_vars.push(quote!({ let #ident = (#arg) as f64; }));
-                vars.push(quote!(let #ident = (#arg) as f64;));
-                args.push(quote!(#ident));
+            Kind::Signed128 => vars.push(
+                quote_spanned!(span=> let #ident = ::hifmt::arg::Signed128::signed128(#value);),
+            ),
+            Kind::Unsigned128 => vars.push(
+                quote_spanned!(span=> let #ident = ::hifmt::arg::Unsigned128::unsigned128(#value);),
+            ),
+            Kind::Hex128 => vars
+                .push(quote_spanned!(span=> let #ident = ::hifmt::arg::Bits128::bits128(#value);)),
+            Kind::Hex | Kind::UpperHex | Kind::Oct | Kind::Bin if nolibc => {
+                vars.push(quote_spanned!(span=>
+                    let #ident = ::hifmt::arg::CInt::to_u64(::hifmt::arg::Bits::bits(#value));
+                ));
             }
-            _ => {
-                test_vars.push(quote!({ let #ident = (#arg) as i64; }));
-                vars.push(quote!(let #ident = (#arg) as i64;));
-                args.push(quote!(#ident));
+            Kind::Hex | Kind::UpperHex | Kind::Oct => {
+                vars.push(quote_spanned!(span=> let #ident = ::hifmt::arg::Bits::bits(#value);));
+                args.push((quote!(#ident), true));
             }
+            Kind::Bin => unreachable!(),
         }
+        lowered.push(Lowered {
+            ident,
+            width,
+            precision,
+            args: args.len(),
+        });
     }
 
-    f(&test_vars, &vars, &args, &literal, &pieces, &idents)
+    f(&vars, &args, &pieces, &lowered)
 }
 
-fn gen_literal(pieces: &[Piece]) -> String {
-    let mut buf = String::new();
-    pieces.iter().all(|piece| {
-        match piece {
-            Piece::Literal(s) => buf.push_str(s),
-            Piece::CStr => buf.push_str("%s"),
-            Piece::Pointer => buf.push_str("%p"),
-            Piece::Str => buf.push_str("%.*s"),
-            Piece::Bytes => buf.push_str("%.*s"),
-            Piece::Signed => buf.push_str("%lld"),
-            Piece::Unsigned => buf.push_str("%llu"),
-            Piece::Hex => buf.push_str("%llx"),
-            Piece::Char => buf.push_str("%.*s"),
-            Piece::CChar => buf.push_str("%c"),
-            Piece::Double => buf.push_str("%e"),
-        }
-        true
+/// 生成printf的格式字符串模板: 字面量中的`%`转义为`%%`, 字面量中间不允许出现`\0`, 末尾补`\0`.
+/// 整数参数的长度修饰符位置用0xFF占位, 由`::hifmt::cfmt`在编译期根据参数类型替换为""或者"ll".
+fn gen_literal(pieces: &[Piece], span: Span) -> parse::Result<Vec<u8>> {
+    let mut buf = vec![];
+    for piece in pieces {
+        let arg = match piece {
+            Piece::Literal(s) => {
+                if s.contains('\0') {
+                    return Err(parse::Error::new(
+                        span,
+                        "invalid format string: interior NUL byte is not allowed",
+                    ));
+                }
+                buf.extend_from_slice(s.replace('%', "%%").as_bytes());
+                continue;
+            }
+            Piece::Arg(arg) => arg,
+        };
+        let spec = &arg.spec;
+        if spec.fill.is_some_and(|fill| fill != ' ') || spec.align == Some('^') {
+            return Err(parse::Error::new(
+                span,
+                "invalid format string: fill character and `^` alignment are not supported by printf",
+            ));
+        }
+        let int_str = is_int_str(arg);
+        if int_str && (spec.precision == Count::Arg || spec.zero && spec.width == Count::Arg) {
+            return Err(parse::Error::new(
+                span,
+                "invalid format string: `*` precision and `0` with `*` width of {:d128}, {:u128}, {:x128}, {:b}, {:#o} and {:#X} are not supported by printf",
+            ));
+        }
+        if int_str && int_zeros(spec) {
+            buf.extend_from_slice(b"%*.*s%.*d%*.*s");
+            continue;
+        }
+        buf.push(b'%');
+        if spec.align == Some('<') {
+            buf.push(b'-');
+        }
+        if spec.sign && !int_str {
+            buf.push(b'+');
+        }
+        if spec.alternate && !int_str {
+            buf.push(b'#');
+        }
+        if spec.zero && !int_str {
+            buf.push(b'0');
+        }
+        match spec.width {
+            Count::Is(n) => buf.extend_from_slice(n.to_string().as_bytes()),
+            Count::Arg => buf.push(b'*'),
+            Count::None => {}
+        }
+        if matches!(arg.kind, Kind::Str | Kind::Bytes | Kind::Char | Kind::CStr) || int_str {
+            buf.extend_from_slice(b".*s");
+            continue;
+        }
+        match spec.precision {
+            Count::Is(n) => buf.extend_from_slice(format!(".{}", n).as_bytes()),
+            Count::Arg => buf.extend_from_slice(b".*"),
+            Count::None => {}
+        }
+        if matches!(
+            arg.kind,
+            Kind::Signed | Kind::Unsigned | Kind::Hex | Kind::UpperHex | Kind::Oct
+        ) {
+            buf.extend_from_slice(&[0xFF, arg.kind.int_type()]);
+            continue;
+        }
+        buf.extend_from_slice(match arg.kind {
+            Kind::Pointer => b"p",
+            Kind::CChar => b"c",
+            Kind::Exp => b"e",
+            Kind::Fixed => b"f",
+            Kind::General => b"g",
+            Kind::HexFloat => b"a",
+            _ => unreachable!(),
+        });
+    }
+    buf.push(0);
+    Ok(buf)
+}
+
+/// `is_int_str`的整数是否需要在前缀和数字之间补0
+fn int_zeros(spec: &Spec) -> bool {
+    matches!(spec.precision, Count::Is(_))
+        || spec.zero && spec.width != Count::None && spec.align != Some('<')
+}
+
+/// libc模式下printf无法直接输出, 需要由`::hifmt::arg::IntStr`先转换为字符串的整数格式
+fn is_int_str(arg: &Arg) -> bool {
+    match arg.kind {
+        Kind::Signed128 | Kind::Unsigned128 | Kind::Hex128 | Kind::Bin => true,
+        Kind::Oct | Kind::UpperHex => arg.spec.alternate,
+        _ => false,
+    }
+}
+
+/// `libc_call`调用的printf系列函数: 用户声明的`dprintf`/`snprintf`,
+/// 或者`Args`转发时传入的函数指针, 此时`ty`为函数指针的类型.
+struct Callee {
+    func: proc_macro2::TokenStream,
+    ty: Option<proc_macro2::TokenStream>,
+}
+
+impl Callee {
+    fn name(func: proc_macro2::TokenStream) -> Self {
+        Callee { func, ty: None }
+    }
+}
+
+/// 生成对`snprintf`/`dprintf`的调用. `{:rs}`/`{:rb}`含有`\0`时`%.*s`会提前结束, 此时改为调用一次
+/// `::hifmt::arg::write_split`, 由其按转换说明逐个输出; `out`是`::hifmt::arg::Out`中对应`callee`的成员.
+fn libc_call(
+    callee: &Callee,
+    pre_args: &[(proc_macro2::TokenStream, proc_macro2::TokenStream)],
+    out: proc_macro2::TokenStream,
+    pieces: &[Piece],
+    args: &[(proc_macro2::TokenStream, bool)],
+    lowered: &[Lowered],
+    span: Span,
+) -> parse::Result<proc_macro2::TokenStream> {
+    // `{:rs}`/`{:rb}`的参数以及对应的长度在args中的位置, 长度之后是指针
+    let mut strs = vec![];
+    let args_iter = pieces.iter().filter_map(|piece| match piece {
+        Piece::Literal(_) => None,
+        Piece::Arg(arg) => Some(arg),
     });
-    buf
+    for (
+        arg,
+        Lowered {
+            ident, args: end, ..
+        },
+    ) in args_iter.zip(lowered)
+    {
+        if matches!(arg.kind, Kind::Str | Kind::Bytes) {
+            strs.push((ident, end - 2));
+        }
+    }
+    if strs.is_empty() {
+        return libc_segment(callee, pre_args, pieces, args, span, None);
+    }
+    let func = &callee.func;
+    let pre_values = pre_args.iter().map(|(value, _)| value);
+    let mut values = vec![];
+    let mut idx = 0;
+    while idx < args.len() {
+        match strs.iter().find(|(_, len)| *len == idx) {
+            Some((ident, _)) => {
+                values.push(quote!(::hifmt::arg::CArg::Bytes(#ident)));
+                idx += 2;
+            }
+            None => {
+                let value = &args[idx].0;
+                values.push(quote!(::hifmt::arg::VaArg::va_arg(#value)));
+                idx += 1;
+            }
+        }
+    }
+    let idents = strs.iter().map(|(ident, _)| ident);
+    let split = Split {
+        strs: quote!(&[#(#idents),*]),
+        out: quote!(::hifmt::arg::Out::#out(#func, #(#pre_values),*)),
+        args: quote!(&[#(#values),*]),
+    };
+    libc_segment(callee, pre_args, pieces, args, span, Some(split))
+}
+
+/// `libc_call`在`{:rs}`/`{:rb}`含有`\0`时传给`::hifmt::arg::write_split`的参数
+struct Split {
+    strs: proc_macro2::TokenStream,
+    out: proc_macro2::TokenStream,
+    args: proc_macro2::TokenStream,
+}
+
+/// 生成一次`snprintf`/`dprintf`调用. 有整数参数时, 格式字符串中的长度修饰符依赖参数类型,
+/// 这里生成一个泛型函数完成调用, 在其中根据`::hifmt::arg::CInt`在编译期生成格式字符串常量.
+/// 参数类型在泛型函数内才需要确定, 未标注类型的整数变量仍按i32推导.
+fn libc_segment(
+    callee: &Callee,
+    pre_args: &[(proc_macro2::TokenStream, proc_macro2::TokenStream)],
+    pieces: &[Piece],
+    args: &[(proc_macro2::TokenStream, bool)],
+    span: Span,
+    split: Option<Split>,
+) -> parse::Result<proc_macro2::TokenStream> {
+    let template = gen_literal(pieces, span)?;
+    let values: Vec<_> = args.iter().map(|(value, _)| value).collect();
+    let pre_types: Vec<_> = pre_args.iter().map(|(_, ty)| ty).collect();
+    let pre_args: Vec<_> = pre_args.iter().map(|(value, _)| value).collect();
+    let func = &callee.func;
+    // 含有`\0`时同样的格式字符串和参数交给`write_split`
+    let with_split = |call: proc_macro2::TokenStream, format: proc_macro2::TokenStream| match &split
+    {
+        None => call,
+        Some(Split { strs, out, args }) => quote! {
+            if !::hifmt::arg::has_nul(#strs) {
+                #call
+            } else {
+                unsafe { ::hifmt::arg::write_split(#out, #format, #args) }
+            }
+        },
+    };
+    if args.iter().all(|(_, int)| !int) {
+        let format = syn::LitByteStr::new(&template, span);
+        let call = quote!(unsafe { #func(#(#pre_args,)* #format.as_ptr(), #(#values),*) });
+        return Ok(with_split(call, quote!(#format)));
+    }
+
+    let len = template.len() + args.iter().filter(|(_, int)| *int).count();
+    let template = syn::LitByteStr::new(&template, span);
+    let pre_names: Vec<_> = (0..pre_args.len())
+        .map(|idx| format_ident!("p{}", idx))
+        .collect();
+    let types: Vec<_> = (0..args.len())
+        .map(|idx| format_ident!("A{}", idx))
+        .collect();
+    let names: Vec<_> = (0..args.len())
+        .map(|idx| format_ident!("a{}", idx))
+        .collect();
+    let ints: Vec<_> = types
+        .iter()
+        .zip(args)
+        .filter(|(_, (_, int))| *int)
+        .map(|(ty, _)| ty)
+        .collect();
+    let int_values = values
+        .iter()
+        .zip(args)
+        .filter(|(_, (_, int))| *int)
+        .map(|(value, _)| value);
+    // 函数指针不能在内部函数中直接使用, 作为第一个参数传入
+    let (callee_param, callee_arg) = match &callee.ty {
+        Some(ty) => (quote!(#func: #ty,), quote!(#func,)),
+        None => (quote!(), quote!()),
+    };
+    let call = with_split(
+        quote!(_hifmt_call(#callee_arg #(#pre_args,)* (#(#values,)*))),
+        quote!(_hifmt_format(&(#(#int_values,)*))),
+    );
+    let format_fn = split.as_ref().map(|_| {
+        quote! {
+            #[inline(always)]
+            fn _hifmt_format<T: _hifmt_Format>(_: &T) -> &'static [u8] {
+                T::FORMAT
+            }
+        }
+    });
+    Ok(quote! {{
+        trait _hifmt_Format {
+            const FORMAT: &'static [u8; #len];
+        }
+        impl<#(#ints: ::hifmt::arg::CInt),*> _hifmt_Format for (#(#ints,)*) {
+            const FORMAT: &'static [u8; #len] = &::hifmt::cfmt(#template, &[#(#ints::LEN),*]);
+        }
+        #[inline(always)]
+        fn _hifmt_call<#(#types),*>(#callee_param #(#pre_names: #pre_types,)* (#(#names,)*): (#(#types,)*)) -> i32
+        where
+            (#(#ints,)*): _hifmt_Format,
+        {
+            let format = <(#(#ints,)*) as _hifmt_Format>::FORMAT;
+            unsafe { #func(#(#pre_names,)* format.as_ptr(), #(#names),*) }
+        }
+        #format_fn
+        #call
+    }})
 }
 
 struct Input {
@@ -413,6 +1410,24 @@ impl Parse for Input {
     }
 }
 
+// `format_array!`的参数: 可选的长度N, 之后同`print!`
+struct ArrayInput {
+    len: Option<Expr>,
+    rest: proc_macro2::TokenStream,
+}
+
+impl Parse for ArrayInput {
+    fn parse(input: ParseStream) -> parse::Result<Self> {
+        let mut len = None;
+        if !input.peek(LitStr) {
+            len = Some(input.parse()?);
+            let _: Token![,] = input.parse()?;
+        }
+        let rest = input.parse()?;
+        Ok(ArrayInput { len, rest })
+    }
+}
+
 struct BufInput {
     buf: Expr,
     input: Input,
@@ -429,6 +1444,11 @@ impl Parse for BufInput {
 
 enum Piece<'a> {
     Literal(Cow<'a, str>),
+    Arg(Arg),
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
     CStr,
     Pointer,
     CChar,
@@ -436,18 +1456,154 @@ enum Piece<'a> {
     Str,
     Bytes,
     Hex,
+    UpperHex,
+    Oct,
+    Bin,
     Unsigned,
     Signed,
-    Double,
+    Hex128,
+    Unsigned128,
+    Signed128,
+    Exp,
+    Fixed,
+    General,
+    HexFloat,
+}
+
+impl Kind {
+    fn parse(ty: &str) -> Option<Kind> {
+        Some(match ty {
+            "cs" => Kind::CStr,
+            "p" => Kind::Pointer,
+            "rs" => Kind::Str,
+            "rb" => Kind::Bytes,
+            "x" => Kind::Hex,
+            "X" => Kind::UpperHex,
+            "o" => Kind::Oct,
+            "b" => Kind::Bin,
+            "d" => Kind::Signed,
+            "u" => Kind::Unsigned,
+            "d128" => Kind::Signed128,
+            "u128" => Kind::Unsigned128,
+            "x128" => Kind::Hex128,
+            "e" => Kind::Exp,
+            "f" => Kind::Fixed,
+            "g" => Kind::General,
+            "a" => Kind::HexFloat,
+            "cc" => Kind::CChar,
+            "rc" => Kind::Char,
+            _ => return None,
+        })
+    }
+
+    /// 整数的格式字符, 同时也是`::hifmt::arg::IntStr`的`ty`
+    fn int_type(self) -> u8 {
+        match self {
+            Kind::Signed | Kind::Signed128 => b'd',
+            Kind::Unsigned | Kind::Unsigned128 => b'u',
+            Kind::Hex | Kind::Hex128 => b'x',
+            Kind::UpperHex => b'X',
+            Kind::Oct => b'o',
+            Kind::Bin => b'b',
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Default, PartialEq)]
+enum Count {
+    #[default]
+    None,
+    Is(usize),
+    // `*`: 由参数指定
+    Arg,
+}
+
+// `[[fill]align][+][#][0][width][.precision]`
+#[derive(Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: bool,
+    alternate: bool,
+    zero: bool,
+    width: Count,
+    precision: Count,
+}
+
+impl Spec {
+    fn is_plain(&self) -> bool {
+        self.fill.is_none()
+            && self.align.is_none()
+            && !self.sign
+            && !self.alternate
+            && !self.zero
+            && self.width == Count::None
+            && self.precision == Count::None
+    }
+}
+
+// `{0:d}`, `{name:d}`或者`{:d}`引用的参数
+enum ArgRef {
+    Next,
+    Index(usize),
+    Name(String),
+}
+
+struct Arg {
+    value: ArgRef,
+    kind: Kind,
+    spec: Spec,
 }
 
-impl Piece<'_> {
-    fn is_literal(&self) -> bool {
-        matches!(self, Piece::Literal(_))
+impl Arg {
+    fn check(&self, ty: &str, span: Span) -> parse::Result<()> {
+        let float = matches!(
+            self.kind,
+            Kind::Exp | Kind::Fixed | Kind::General | Kind::HexFloat
+        );
+        if float && cfg!(feature = "no-float") {
+            return Err(parse::Error::new(
+                span,
+                format!(
+                    "invalid format string: {{:{}}} is disabled by the `no-float` feature",
+                    ty
+                ),
+            ));
+        }
+        // (+, #, 0, precision)
+        let allowed = match self.kind {
+            Kind::Signed | Kind::Signed128 => (true, false, true, true),
+            Kind::Unsigned | Kind::Unsigned128 => (false, false, true, true),
+            Kind::Hex | Kind::Hex128 | Kind::UpperHex | Kind::Oct | Kind::Bin => {
+                (false, true, true, true)
+            }
+            Kind::Exp | Kind::Fixed | Kind::General | Kind::HexFloat => (true, true, true, true),
+            Kind::CStr | Kind::Str | Kind::Bytes => (false, false, false, true),
+            Kind::Pointer | Kind::CChar | Kind::Char => (false, false, false, false),
+        };
+        let spec = &self.spec;
+        for (used, allowed, flag) in [
+            (spec.sign, allowed.0, "`+`"),
+            (spec.alternate, allowed.1, "`#`"),
+            (spec.zero, allowed.2, "`0`"),
+            (spec.precision != Count::None, allowed.3, "precision"),
+        ] {
+            if used && !allowed {
+                return Err(parse::Error::new(
+                    span,
+                    format!(
+                        "invalid format string: {{:{}}} does not support {}",
+                        ty, flag
+                    ),
+                ));
+            }
+        }
+        Ok(())
     }
 }
 
-fn parse(mut format: &str, span: Span) -> parse::Result<Vec<Piece>> {
+fn parse(mut format: &str, span: Span) -> parse::Result<Vec<Piece<'_>>> {
     let mut pieces = vec![];
     let mut buf = String::new();
     loop {
@@ -466,30 +1622,14 @@ fn parse(mut format: &str, span: Span) -> parse::Result<Vec<Piece>> {
                 break;
             }
             (head, Some(tail)) => {
-                const CSTR: &str = ":cs}";
-                const POINTER: &str = ":p}";
-                const STR: &str = ":rs}";
-                const BYTES: &str = ":rb}";
-                const HEX: &str = ":x}";
-                const SIGNED: &str = ":d}";
-                const UNSIGNED: &str = ":u}";
-                const DOUBLE: &str = ":e}";
-                const CCHAR: &str = ":cc}";
-                const CHAR: &str = ":rc}";
                 const ESCAPE_BRACE: &str = "{";
 
                 let head = head.unwrap_or("");
-                if tail.starts_with(CSTR)
-                    || tail.starts_with(POINTER)
-                    || tail.starts_with(STR)
-                    || tail.starts_with(BYTES)
-                    || tail.starts_with(HEX)
-                    || tail.starts_with(SIGNED)
-                    || tail.starts_with(UNSIGNED)
-                    || tail.starts_with(DOUBLE)
-                    || tail.starts_with(CCHAR)
-                    || tail.starts_with(CHAR)
-                {
+                if let Some(tail_tail) = tail.strip_prefix(ESCAPE_BRACE) {
+                    buf.push_str(&unescape(head, span)?);
+                    buf.push('{');
+                    format = tail_tail;
+                } else if let Some((arg, tail_tail)) = tail.split_once('}') {
                     if buf.is_empty() {
                         if !head.is_empty() {
                             pieces.push(Piece::Literal(unescape(head, span)?));
@@ -498,45 +1638,13 @@ fn parse(mut format: &str, span: Span) -> parse::Result<Vec<Piece>> {
                         buf.push_str(&unescape(head, span)?);
                         pieces.push(Piece::Literal(Cow::Owned(mem::take(&mut buf))));
                     }
-
-                    if let Some(tail_tail) = tail.strip_prefix(CSTR) {
-                        pieces.push(Piece::CStr);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(POINTER) {
-                        pieces.push(Piece::Pointer);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(STR) {
-                        pieces.push(Piece::Str);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(BYTES) {
-                        pieces.push(Piece::Bytes);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(HEX) {
-                        pieces.push(Piece::Hex);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(SIGNED) {
-                        pieces.push(Piece::Signed);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(UNSIGNED) {
-                        pieces.push(Piece::Unsigned);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(CCHAR) {
-                        pieces.push(Piece::CChar);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(CHAR) {
-                        pieces.push(Piece::Char);
-                        format = tail_tail;
-                    } else if let Some(tail_tail) = tail.strip_prefix(DOUBLE) {
-                        pieces.push(Piece::Double);
-                        format = tail_tail;
-                    }
-                } else if let Some(tail_tail) = tail.strip_prefix(ESCAPE_BRACE) {
-                    buf.push_str(&unescape(head, span)?);
-                    buf.push('{');
+                    pieces.push(Piece::Arg(parse_arg(arg, span)?));
                     format = tail_tail;
                 } else {
-                    return Err(parse::Error::new(span,
-                        "invalid format string: expected {:d}, {:u}, {:x}, {:e}, {:p}, {:cs}, {:rs}, {:rb} {:cc} {:rc} {{"));
+                    return Err(parse::Error::new(
+                        span,
+                        "invalid format string: unmatched left brace",
+                    ));
                 }
             }
         }
@@ -545,7 +1653,87 @@ fn parse(mut format: &str, span: Span) -> parse::Result<Vec<Piece>> {
     Ok(pieces)
 }
 
-fn unescape(mut format: &str, span: Span) -> parse::Result<Cow<str>> {
+fn parse_arg(arg: &str, span: Span) -> parse::Result<Arg> {
+    const ERR: &str = "invalid format string: expected {:d}, {:u}, {:x}, {:X}, {:o}, {:b}, {:d128}, {:u128}, {:x128}, {:e}, {:f}, {:g}, {:a}, {:p}, {:cs}, {:rs}, {:rb} {:cc} {:rc} {{";
+    let (value, spec) = match arg.split_once(':') {
+        Some(arg) => arg,
+        None => return Err(parse::Error::new(span, ERR)),
+    };
+    let value = if value.is_empty() {
+        ArgRef::Next
+    } else if let Ok(idx) = value.parse() {
+        ArgRef::Index(idx)
+    } else {
+        ArgRef::Name(value.to_string())
+    };
+
+    let mut s = Spec::default();
+    let mut rest = spec;
+    let is_align = |c: char| matches!(c, '<' | '>' | '^');
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(fill), Some(align)) if is_align(align) => {
+            s.fill = Some(fill);
+            s.align = Some(align);
+            rest = &rest[fill.len_utf8() + 1..];
+        }
+        (Some(align), _) if is_align(align) => {
+            s.align = Some(align);
+            rest = &rest[1..];
+        }
+        _ => {}
+    }
+    if let Some(r) = rest.strip_prefix('+') {
+        s.sign = true;
+        rest = r;
+    }
+    if let Some(r) = rest.strip_prefix('#') {
+        s.alternate = true;
+        rest = r;
+    }
+    if let Some(r) = rest.strip_prefix('0') {
+        s.zero = true;
+        rest = r;
+    }
+    (s.width, rest) = parse_count(rest, span)?;
+    if let Some(r) = rest.strip_prefix('.') {
+        (s.precision, rest) = parse_count(r, span)?;
+        if s.precision == Count::None {
+            return Err(parse::Error::new(
+                span,
+                "invalid format string: expected precision after `.`",
+            ));
+        }
+    }
+
+    let kind = Kind::parse(rest).ok_or_else(|| parse::Error::new(span, ERR))?;
+    let arg = Arg {
+        value,
+        kind,
+        spec: s,
+    };
+    arg.check(rest, span)?;
+    Ok(arg)
+}
+
+fn parse_count(s: &str, span: Span) -> parse::Result<(Count, &str)> {
+    if let Some(rest) = s.strip_prefix('*') {
+        return Ok((Count::Arg, rest));
+    }
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    if end == 0 {
+        return Ok((Count::None, s));
+    }
+    match s[..end].parse() {
+        Ok(n) => Ok((Count::Is(n), &s[end..])),
+        Err(_) => Err(parse::Error::new(
+            span,
+            "invalid format string: width or precision is too large",
+        )),
+    }
+}
+
+fn unescape(mut format: &str, span: Span) -> parse::Result<Cow<'_, str>> {
     if format.contains('}') {
         let mut buf = String::new();
         while format.contains('}') {
@@ -0,0 +1,819 @@
+//! 参数转换: `hifmt`宏生成的代码通过这里的trait把整数参数转换为C的整数类型.
+//! 同C的整数提升一样, 32位及以下的整数按`int`输出, 只有64位整数才需要`long long`,
+//! nolibc模式下也只有64位整数才会用到64位除法. 无符号输出时保持参数本身的宽度, 不做符号扩展.
+//! 128位整数没有对应的C类型, 使用单独的`{:d128}`, `{:u128}`, `{:x128}`格式,
+//! libc模式下同printf不支持的`{:b}`等一起由`IntStr`先转换为字符串.
+
+use core::ffi::CStr;
+use core::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize, Wrapping,
+};
+use core::sync::atomic;
+
+#[cfg(feature = "nolibc")]
+use crate::{Formatter, Spec};
+
+/// 可以被数值格式(`{:d}`, `{:x}`, `{:e}`, `{:cc}`等)输出的参数类型.
+/// 宏先通过`hi_arg`把参数转换为基本类型`Prim`, 再按基本类型输出, 生成的代码不涉及`core::fmt`.
+/// 基本类型和`NonZero***`, `Wrapping<T>`, 原子类型已经实现, 用户可以为自己的newtype实现.
+pub trait HiArg {
+    /// 整数, 浮点数, `bool`或者`char`
+    type Prim;
+    fn hi_arg(&self) -> Self::Prim;
+}
+
+macro_rules! impl_hi_arg {
+    ($($ty: ty),*) => {$(
+        impl HiArg for $ty {
+            type Prim = $ty;
+            #[inline(always)]
+            fn hi_arg(&self) -> $ty {
+                *self
+            }
+        }
+    )*};
+}
+
+impl_hi_arg!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64, bool, char);
+
+impl<T: HiArg + ?Sized> HiArg for &T {
+    type Prim = T::Prim;
+    #[inline(always)]
+    fn hi_arg(&self) -> T::Prim {
+        (**self).hi_arg()
+    }
+}
+
+impl<T: HiArg> HiArg for Wrapping<T> {
+    type Prim = T::Prim;
+    #[inline(always)]
+    fn hi_arg(&self) -> T::Prim {
+        self.0.hi_arg()
+    }
+}
+
+macro_rules! impl_hi_arg_nonzero {
+    ($($ty: ty, $prim: ty;)*) => {$(
+        impl HiArg for $ty {
+            type Prim = $prim;
+            #[inline(always)]
+            fn hi_arg(&self) -> $prim {
+                self.get()
+            }
+        }
+    )*};
+}
+
+impl_hi_arg_nonzero! {
+    NonZeroI8, i8; NonZeroI16, i16; NonZeroI32, i32; NonZeroI64, i64; NonZeroI128, i128; NonZeroIsize, isize;
+    NonZeroU8, u8; NonZeroU16, u16; NonZeroU32, u32; NonZeroU64, u64; NonZeroU128, u128; NonZeroUsize, usize;
+}
+
+// 原子类型输出`Relaxed`读取的快照
+macro_rules! impl_hi_arg_atomic {
+    ($($width: literal: $($ty: ident, $prim: ty;)*)*) => {$($(
+        #[cfg(target_has_atomic = $width)]
+        impl HiArg for atomic::$ty {
+            type Prim = $prim;
+            #[inline(always)]
+            fn hi_arg(&self) -> $prim {
+                self.load(atomic::Ordering::Relaxed)
+            }
+        }
+    )*)*};
+}
+
+impl_hi_arg_atomic! {
+    "8": AtomicBool, bool; AtomicI8, i8; AtomicU8, u8;
+    "16": AtomicI16, i16; AtomicU16, u16;
+    "32": AtomicI32, i32; AtomicU32, u32;
+    "64": AtomicI64, i64; AtomicU64, u64;
+    "ptr": AtomicIsize, isize; AtomicUsize, usize;
+}
+
+/// `{:cs}`的参数: C字符串指针以及最多读取的字节数.
+/// `&CStr`和裸指针读取到0为止, 字节数组和切片最多读取到末尾, 不会越界.
+pub trait CStrArg {
+    fn c_str(&self) -> (*const u8, usize);
+}
+
+impl CStrArg for &CStr {
+    #[inline(always)]
+    fn c_str(&self) -> (*const u8, usize) {
+        (self.as_ptr() as *const u8, usize::MAX)
+    }
+}
+
+macro_rules! impl_cstr_ptr {
+    ($($ty: ty),*) => {$(
+        impl CStrArg for $ty {
+            #[inline(always)]
+            fn c_str(&self) -> (*const u8, usize) {
+                (*self as *const u8, usize::MAX)
+            }
+        }
+    )*};
+}
+
+impl_cstr_ptr!(*const i8, *const u8, *mut i8, *mut u8);
+
+macro_rules! impl_cstr_bytes {
+    ($($ty: ty),*) => {$(
+        impl<const N: usize> CStrArg for $ty {
+            #[inline(always)]
+            fn c_str(&self) -> (*const u8, usize) {
+                (self.as_ptr(), N)
+            }
+        }
+    )*};
+}
+
+impl_cstr_bytes!(&[u8; N], &mut [u8; N]);
+
+impl CStrArg for &[u8] {
+    #[inline(always)]
+    fn c_str(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+impl CStrArg for &mut [u8] {
+    #[inline(always)]
+    fn c_str(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+/// 传给printf或者`Formatter`的整数类型: i32/u32对应`%d`/`%u`, i64/u64对应`%lld`/`%llu`
+pub trait CInt: Copy {
+    /// printf的长度修饰符
+    #[cfg(not(feature = "nolibc"))]
+    const LEN: &'static [u8];
+
+    #[cfg(feature = "nolibc")]
+    fn write<F: Formatter + ?Sized>(self, f: &mut F) -> usize;
+    #[cfg(feature = "nolibc")]
+    fn write_spec<F: Formatter + ?Sized>(self, f: &mut F, spec: &Spec) -> usize;
+    /// 2/8/16进制只需要移位, nolibc中统一按u64输出
+    #[cfg(feature = "nolibc")]
+    fn to_u64(self) -> u64;
+}
+
+macro_rules! impl_cint {
+    ($($ty: ty, $len: literal, $write: ident, $write_spec: ident;)*) => {$(
+        impl CInt for $ty {
+            #[cfg(not(feature = "nolibc"))]
+            const LEN: &'static [u8] = $len;
+
+            #[cfg(feature = "nolibc")]
+            fn write<F: Formatter + ?Sized>(self, f: &mut F) -> usize {
+                f.$write(self)
+            }
+            #[cfg(feature = "nolibc")]
+            fn write_spec<F: Formatter + ?Sized>(self, f: &mut F, spec: &Spec) -> usize {
+                f.$write_spec(self, spec)
+            }
+            #[cfg(feature = "nolibc")]
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+        }
+    )*};
+}
+
+impl_cint! {
+    i32, b"", write_i32, write_i32_spec;
+    u32, b"", write_u32, write_u32_spec;
+    i64, b"ll", write_i64, write_i64_spec;
+    u64, b"ll", write_u64, write_u64_spec;
+}
+
+/// `{:d}`的参数
+pub trait Signed {
+    type C: CInt;
+    fn signed(self) -> Self::C;
+}
+
+/// `{:u}`的参数
+pub trait Unsigned {
+    type C: CInt;
+    fn unsigned(self) -> Self::C;
+}
+
+/// `{:x}`, `{:X}`, `{:o}`, `{:b}`的参数, 有符号数按参数本身宽度的补码输出, `-1_i8`输出`ff`,
+/// 而不是C的整数提升之后的`ffffffff`
+pub trait Bits {
+    type C: CInt;
+    fn bits(self) -> Self::C;
+}
+
+/// `{:d128}`的参数
+pub trait Signed128 {
+    fn signed128(self) -> i128;
+}
+
+/// `{:u128}`的参数
+pub trait Unsigned128 {
+    fn unsigned128(self) -> u128;
+}
+
+/// `{:x128}`的参数, 同`Bits`一样保持参数本身的宽度
+pub trait Bits128 {
+    fn bits128(self) -> u128;
+}
+
+/// `{:e}`, `{:f}`, `{:g}`, `{:a}`的参数, 宏按`f32`特性选择转换为f64或者f32
+pub trait Float {
+    fn f64(self) -> f64;
+    fn f32(self) -> f32;
+}
+
+/// `{:cc}`的参数
+pub trait CChar {
+    fn c_char(self) -> i32;
+}
+
+/// `*`宽度和精度的参数, 同printf一样按`int`传递, 负的宽度表示左对齐, 负的精度表示没有精度.
+/// 超出`i32`范围的值取`i32::MAX`或者`-i32::MAX`, 不会回绕成负数而改变对齐方式
+pub trait Count {
+    fn count(self) -> i32;
+}
+
+macro_rules! impl_int {
+    ($trait: ident, $method: ident, $c: ty: $($ty: ty $(as $via: ty)?),*) => {$(
+        impl $trait for $ty {
+            type C = $c;
+            #[inline(always)]
+            fn $method(self) -> $c {
+                self $(as $via)? as $c
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_to {
+    ($trait: ident, $method: ident, $c: ty: $($ty: ty $(as $via: ty)?),*) => {$(
+        impl $trait for $ty {
+            #[inline(always)]
+            fn $method(self) -> $c {
+                self $(as $via)? as $c
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_count {
+    ($($ty: ty),*) => {$(
+        impl Count for $ty {
+            #[inline(always)]
+            #[allow(unused_comparisons)]
+            fn count(self) -> i32 {
+                match i32::try_from(self) {
+                    Ok(n) => n.max(-i32::MAX),
+                    Err(_) if self < 0 => -i32::MAX,
+                    Err(_) => i32::MAX,
+                }
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_float {
+    ($($ty: ty),*) => {$(
+        impl Float for $ty {
+            #[inline(always)]
+            fn f64(self) -> f64 {
+                self as f64
+            }
+            #[inline(always)]
+            fn f32(self) -> f32 {
+                self as f32
+            }
+        }
+    )*};
+}
+
+impl_int!(Signed, signed, i32: i8, i16, i32, u8, u16);
+impl_int!(Signed, signed, i64: i64, u32);
+impl_int!(Unsigned, unsigned, u32: u8, u16, u32);
+impl_int!(Unsigned, unsigned, u64: u64);
+impl_int!(Bits, bits, u32: i8 as u8, i16 as u16, i32, u8, u16, u32);
+impl_int!(Bits, bits, u64: i64, u64);
+impl_to!(Signed128, signed128, i128: i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, usize);
+impl_to!(Unsigned128, unsigned128, u128: u8, u16, u32, u64, u128, usize);
+impl_to!(Bits128, bits128, u128: i8 as u8, i16 as u16, i32 as u32, i64 as u64, i128, isize as usize);
+impl_to!(Bits128, bits128, u128: u8, u16, u32, u64, u128, usize);
+impl_to!(CChar, c_char, i32: i8, u8);
+impl_count!(i32, usize);
+impl_float!(f32);
+// nolibc + f32时浮点参数按f32输出, f64会丢失精度
+#[cfg(not(all(feature = "strict", feature = "nolibc", feature = "f32")))]
+impl_float!(f64);
+
+#[cfg(target_pointer_width = "64")]
+impl_int!(Signed, signed, i64: isize);
+#[cfg(target_pointer_width = "64")]
+impl_int!(Unsigned, unsigned, u64: usize);
+#[cfg(target_pointer_width = "64")]
+impl_int!(Bits, bits, u64: isize, usize);
+#[cfg(not(target_pointer_width = "64"))]
+impl_int!(Signed, signed, i32: isize);
+#[cfg(not(target_pointer_width = "64"))]
+impl_int!(Signed, signed, i64: usize);
+#[cfg(not(target_pointer_width = "64"))]
+impl_int!(Unsigned, unsigned, u32: usize);
+#[cfg(not(target_pointer_width = "64"))]
+impl_int!(Bits, bits, u32: isize, usize);
+
+/// 与`as`转换一致的有损转换, 例如`{:d}`输出浮点数, `{:u}`输出负数.
+/// feature = "strict"时不提供, 使用时在参数处编译报错.
+#[cfg(not(feature = "strict"))]
+mod lossy {
+    use super::*;
+
+    impl_int!(Signed, signed, i32: bool, char);
+    impl_int!(Signed, signed, i64: u64, f32, f64);
+    impl_int!(Unsigned, unsigned, u32: i8 as u8, i16 as u16, i32, bool, char);
+    // 负数按i64的补码输出
+    impl_int!(Unsigned, unsigned, u64: i64, f32 as i64, f64 as i64);
+    impl_int!(Bits, bits, u32: bool, char);
+    impl_int!(Bits, bits, u64: f32 as i64, f64 as i64);
+    impl_to!(Signed128, signed128, i128: u128, bool, char, f32, f64);
+    impl_to!(Unsigned128, unsigned128, u128: i8, i16, i32, i64, i128, isize, bool, char, f32, f64);
+    impl_to!(Bits128, bits128, u128: bool, char, f32, f64);
+    impl_to!(CChar, c_char, i32: i16, i32, i64, i128, isize, u16, u32, u64, u128, usize);
+    impl_to!(CChar, c_char, i32: bool, char, f32, f64);
+    impl_float!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+    impl_count!(i8, i16, i64, i128, isize, u8, u16, u32, u64, u128);
+    impl_to!(Count, count, i32: bool, char, f32, f64);
+
+    #[cfg(target_pointer_width = "64")]
+    impl_int!(Signed, signed, i64: usize);
+    #[cfg(target_pointer_width = "64")]
+    impl_int!(Unsigned, unsigned, u64: isize);
+    #[cfg(not(target_pointer_width = "64"))]
+    impl_int!(Unsigned, unsigned, u32: isize);
+}
+
+/// 按格式字符(d/u, x/X, o, b)把整数转换为数字, 写在buf末尾.
+/// 10进制先按10^19分段, 每段只用64位运算, 128位除法最多执行两次.
+pub(crate) fn u128_buf(mut val: u128, ty: u8, buf: &mut [u8; 128]) -> &[u8] {
+    const CHUNK: u128 = 10_000_000_000_000_000_000;
+    let mut pos = buf.len();
+    let (shift, digits) = match ty {
+        b'x' => (4, b"0123456789abcdef"),
+        b'X' => (4, b"0123456789ABCDEF"),
+        b'o' => (3, b"0123456789abcdef"),
+        b'b' => (1, b"0123456789abcdef"),
+        _ => (0, b"0123456789abcdef"),
+    };
+    if shift > 0 {
+        let mask = (1 << shift) - 1;
+        loop {
+            pos -= 1;
+            buf[pos] = digits[(val & mask) as usize];
+            val >>= shift;
+            if val == 0 {
+                return &buf[pos..];
+            }
+        }
+    }
+    while val > u64::MAX as u128 {
+        let mut chunk = (val % CHUNK) as u64;
+        val /= CHUNK;
+        for _ in 0..19 {
+            pos -= 1;
+            buf[pos] = b'0' + (chunk % 10) as u8;
+            chunk /= 10;
+        }
+    }
+    let mut val = val as u64;
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (val % 10) as u8;
+        val /= 10;
+        if val == 0 {
+            return &buf[pos..];
+        }
+    }
+}
+
+/// 数字之前的符号或者`#`要求的前缀, 同`%#x`一样0不输出前缀
+pub(crate) fn int_prefix(
+    neg: bool,
+    nonzero: bool,
+    ty: u8,
+    sign: bool,
+    alternate: bool,
+) -> &'static [u8] {
+    match ty {
+        _ if neg => b"-",
+        b'd' if sign => b"+",
+        b'x' | b'X' if alternate && nonzero => b"0x",
+        b'o' if alternate && nonzero => b"0o",
+        b'b' if alternate && nonzero => b"0b",
+        _ => b"",
+    }
+}
+
+/// libc模式下printf无法输出的整数: 128位整数, `{:b}`以及`{:#o}`, `{:#X}`的`0o`/`0x`前缀.
+/// 先在栈上按printf的规则转换为前缀和数字, 再按`%.*s`输出, 宽度和对齐仍由printf处理.
+/// 补0的个数与宽度和精度有关, 不写入栈上的缓冲区, 由`pad`计算后按`%.*d`输出.
+#[cfg(not(feature = "nolibc"))]
+pub struct IntStr {
+    pub neg: bool,
+    pub val: u128,
+    /// 格式字符: d/u, x/X, o, b
+    pub ty: u8,
+    pub sign: bool,
+    pub alternate: bool,
+    /// 是否有`0`标志
+    pub zero: bool,
+    pub precision: Option<usize>,
+}
+
+/// `IntStr::pad`的结果, 按`%*.*s%.*d%*.*s`依次输出前缀, 补0和数字
+#[cfg(not(feature = "nolibc"))]
+pub struct IntPad<'a> {
+    /// 右对齐时前缀的宽度, 空格补在前缀之前
+    pub prefix_width: i32,
+    pub prefix: &'a [u8],
+    pub zeros: i32,
+    /// 左对齐时数字的宽度, 为负数, 空格补在数字之后
+    pub digits_width: i32,
+    pub digits: &'a [u8],
+}
+
+#[cfg(not(feature = "nolibc"))]
+impl IntStr {
+    /// 前缀和数字依次写入buf, 返回写入的部分以及前缀的长度.
+    /// 前缀最多2个字节, 数字最多128个字节, 与宽度和精度无关
+    pub fn write<'a>(&self, buf: &'a mut [u8; 130]) -> (&'a [u8], usize) {
+        let mut digits = [0_u8; 128];
+        let digits = match self.precision {
+            Some(0) if self.val == 0 => &[],
+            _ => u128_buf(self.val, self.ty, &mut digits),
+        };
+        let prefix = int_prefix(self.neg, self.val != 0, self.ty, self.sign, self.alternate);
+        let len = prefix.len() + digits.len();
+        buf[..prefix.len()].copy_from_slice(prefix);
+        buf[prefix.len()..len].copy_from_slice(digits);
+        (&buf[..len], prefix.len())
+    }
+
+    /// 有精度或者`0`标志时需要在前缀和数字之间补0, width同printf的`*`宽度, 负数表示左对齐
+    pub fn pad<'a>(&self, buf: &'a mut [u8; 130], width: i32) -> IntPad<'a> {
+        let (s, prefix_len) = self.write(buf);
+        let (prefix, digits) = s.split_at(prefix_len);
+        let (left, width) = (
+            width < 0,
+            width.unsigned_abs().min(i32::MAX as u32) as usize,
+        );
+        let zeros = match self.precision {
+            Some(precision) => precision.saturating_sub(digits.len()),
+            None if self.zero && !left => width.saturating_sub(s.len()),
+            None => 0,
+        };
+        let zeros = zeros.min(i32::MAX as usize - s.len());
+        let spaces = width.saturating_sub(s.len() + zeros);
+        let (prefix_width, digits_width) = match left {
+            true => (0, -((digits.len() + spaces) as i32)),
+            false => ((prefix.len() + spaces) as i32, 0),
+        };
+        IntPad {
+            prefix_width,
+            prefix,
+            zeros: zeros as i32,
+            digits_width,
+            digits,
+        }
+    }
+}
+
+/// libc模式下`{:rs}`/`{:rb}`的参数都不含`\0`时整个格式串只需要调用一次printf
+#[cfg(not(feature = "nolibc"))]
+#[inline(never)]
+pub fn has_nul(strs: &[&[u8]]) -> bool {
+    strs.iter().any(|bytes| bytes.contains(&0))
+}
+
+/// `write_split`的输出目标: `dprintf`和fd, 或者`snprintf`和buf的指针, 长度
+#[cfg(not(feature = "nolibc"))]
+pub enum Out {
+    Fd(DprintfFn, i32),
+    Buf(SnprintfFn, *mut u8, usize),
+}
+
+/// 类型擦除之后的printf参数, `{:rs}`/`{:rb}`的长度和指针合并为`Bytes`
+#[cfg(not(feature = "nolibc"))]
+#[derive(Clone, Copy)]
+pub enum CArg<'a> {
+    Int(i32),
+    Long(i64),
+    Double(f64),
+    Ptr(*const u8),
+    Bytes(&'a [u8]),
+}
+
+/// 可以转换为`CArg`的printf参数类型
+#[cfg(not(feature = "nolibc"))]
+pub trait VaArg: Copy {
+    fn va_arg(self) -> CArg<'static>;
+}
+
+#[cfg(not(feature = "nolibc"))]
+macro_rules! impl_va_arg {
+    ($($ty: ty, $variant: ident, $c: ty;)*) => {$(
+        impl VaArg for $ty {
+            #[inline(always)]
+            fn va_arg(self) -> CArg<'static> {
+                CArg::$variant(self as $c)
+            }
+        }
+    )*};
+}
+
+#[cfg(not(feature = "nolibc"))]
+impl_va_arg! {
+    i32, Int, i32; u32, Int, i32; i64, Long, i64; u64, Long, i64; f64, Double, f64; *const u8, Ptr, *const u8;
+}
+
+/// `{:rs}`/`{:rb}`含有`\0`时`%.*s`会提前结束, 改为按转换说明逐个调用printf, 累加每次的返回值.
+/// `format`是宏生成的完整格式字符串, `args`依次是其中每个转换说明的参数.
+/// 某一次出错(返回负数)后不再继续输出, 最终返回该错误.
+/// 不是泛型函数, 所有调用点共用一份代码, 调用点只需要准备参数之后调用一次.
+///
+/// # Safety
+///
+/// 同直接调用printf: `format`和`args`必须一一对应, `Out::Buf`的指针必须可以写入对应的长度
+#[cfg(not(feature = "nolibc"))]
+#[inline(never)]
+pub unsafe fn write_split(out: Out, format: &[u8], args: &[CArg<'_>]) -> i32 {
+    let format = format.strip_suffix(b"\0").unwrap_or(format);
+    let mut args = args.iter().copied();
+    let (mut ret, mut pos) = (0, 0);
+    while pos < format.len() && ret >= 0 {
+        // 字面量按`%.*s`输出, `%%`输出第一个`%`之后跳过第二个
+        let end = format[pos..]
+            .iter()
+            .position(|&b| b == b'%')
+            .map_or(format.len(), |n| pos + n);
+        let escaped = format.get(end + 1) == Some(&b'%');
+        let literal = &format[pos..end + escaped as usize];
+        if !literal.is_empty() {
+            let len = CArg::Int(literal.len() as i32);
+            ret = step(ret, |ret| {
+                printf(&out, ret, c"%.*s", &[len, CArg::Ptr(literal.as_ptr())])
+            });
+        }
+        pos = end + 2 * escaped as usize;
+        if escaped || pos >= format.len() {
+            continue;
+        }
+        // 转换说明: `%`, 标志, 宽度, 精度, 长度修饰符以及格式字符
+        let len = format[pos + 1..]
+            .iter()
+            .position(|b| b.is_ascii_alphabetic() && *b != b'l')
+            .map_or(format.len() - pos, |n| n + 2);
+        let spec = &format[pos..pos + len];
+        pos += len;
+        // 每个`*`一个int参数, 之后是值; `Bytes`已经包含了`.*`的长度
+        let (mut conv, mut count) = ([CArg::Int(0); 3], 0);
+        let stars = spec.iter().filter(|&&b| b == b'*').count();
+        while count <= stars.min(2) {
+            conv[count] = args.next().unwrap_or(CArg::Int(0));
+            count += 1;
+            if matches!(conv[count - 1], CArg::Bytes(_)) {
+                break;
+            }
+        }
+        ret = match conv[..count] {
+            [.., CArg::Bytes(bytes)] => {
+                let flags = spec[1..]
+                    .iter()
+                    .take_while(|b| b"-+#0 ".contains(b))
+                    .count();
+                let width = match conv[0] {
+                    CArg::Int(width) if count == 2 => width,
+                    _ => spec[1 + flags..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_digit())
+                        .fold(0_i32, |n, b| {
+                            n.saturating_mul(10).saturating_add((b - b'0') as i32)
+                        }),
+                };
+                let left = spec[1..1 + flags].contains(&b'-');
+                write_bytes(ret, bytes, width, left, &mut |ret, fmt, n, ptr| {
+                    printf(&out, ret, fmt, &[CArg::Int(n), CArg::Ptr(ptr)])
+                })
+            }
+            _ => {
+                let mut buf = [0_u8; 64];
+                let len = spec.len().min(buf.len() - 1);
+                buf[..len].copy_from_slice(&spec[..len]);
+                let fmt = CStr::from_bytes_until_nul(&buf).unwrap_or(c"");
+                step(ret, |ret| printf(&out, ret, fmt, &conv[..count]))
+            }
+        };
+    }
+    ret
+}
+
+/// 调用一次printf, `args`是转换说明的`*`参数和值, 最多3个.
+/// `Out::Buf`时从已输出的位置继续, 超出buf之后长度为0, 只计算需要的长度
+#[cfg(not(feature = "nolibc"))]
+unsafe fn printf(out: &Out, ret: i32, fmt: &CStr, args: &[CArg<'_>]) -> i32 {
+    macro_rules! call {
+        ($f: ident($($pre: expr),*)) => {
+            match *args {
+                [a] => call!(@value $f($($pre,)* fmt.as_ptr().cast()), a),
+                [CArg::Int(n), a] => call!(@value $f($($pre,)* fmt.as_ptr().cast(), n), a),
+                [CArg::Int(n), CArg::Int(m), a] => {
+                    call!(@value $f($($pre,)* fmt.as_ptr().cast(), n, m), a)
+                }
+                _ => 0,
+            }
+        };
+        (@value $f: ident($($pre: expr),*), $a: expr) => {
+            match $a {
+                CArg::Int(v) => $f($($pre,)* v),
+                CArg::Long(v) => $f($($pre,)* v),
+                CArg::Double(v) => $f($($pre,)* v),
+                CArg::Ptr(v) => $f($($pre,)* v),
+                CArg::Bytes(_) => 0,
+            }
+        };
+    }
+    match *out {
+        Out::Fd(f, fd) => call!(f(fd)),
+        Out::Buf(f, buf, len) => {
+            let offset = (ret as usize).min(len);
+            call!(f(buf.wrapping_add(offset), len - offset))
+        }
+    }
+}
+
+#[cfg(not(feature = "nolibc"))]
+fn step(ret: i32, f: impl FnOnce(i32) -> i32) -> i32 {
+    if ret < 0 {
+        return ret;
+    }
+    match f(ret) {
+        n if n < 0 => n,
+        n => ret.saturating_add(n),
+    }
+}
+
+/// 分段输出`{:rs}`/`{:rb}`: `%.*s`遇到`\0`就会停止, `\0`改由`%c`输出, 宽度对应的空格由`%*s`输出.
+/// `f`的参数依次为已输出的长度, 格式字符串, 以及格式字符串需要的int和指针参数.
+#[cfg(not(feature = "nolibc"))]
+fn write_bytes(
+    mut ret: i32,
+    bytes: &[u8],
+    width: i32,
+    left: bool,
+    f: &mut dyn FnMut(i32, &CStr, i32, *const u8) -> i32,
+) -> i32 {
+    let pad = (width.unsigned_abs() as usize).saturating_sub(bytes.len()) as i32;
+    let left = left || width < 0;
+    let empty = c"".as_ptr().cast::<u8>();
+    if pad > 0 && !left {
+        ret = step(ret, |ret| f(ret, c"%*s", pad, empty));
+    }
+    for (idx, chunk) in bytes.split(|b| *b == 0).enumerate() {
+        if idx > 0 {
+            ret = step(ret, |ret| f(ret, c"%c", 0, empty));
+        }
+        if !chunk.is_empty() {
+            let len = chunk.len().min(i32::MAX as usize) as i32;
+            ret = step(ret, |ret| f(ret, c"%.*s", len, chunk.as_ptr()));
+        }
+    }
+    if pad > 0 && left {
+        ret = step(ret, |ret| f(ret, c"%*s", pad, empty));
+    }
+    ret
+}
+
+/// `sprint!`输出之后保证`&mut str`仍然是合法的UTF-8: 按字节截断可能截断多字节字符,
+/// `{:rb}`也可能写入非UTF-8的字节. 非法的字节逐个替换为`?`, 不改变输出的长度;
+/// 只有输出超出buf时末尾被截断的多字节字符才去掉. 保留的部分之后直到末尾全部填充为0.
+/// `ret`为printf或者`BufFormatter`返回的完整输出长度, 返回保留的字节数以及输出是否超出buf被截断.
+pub fn utf8_fixup(buf: &mut str, ret: i32) -> (usize, bool) {
+    // SAFETY: 返回之前非法的字节都已经替换或者填充为0
+    let bytes = unsafe { buf.as_bytes_mut() };
+    // snprintf和`BufFormatter`都在末尾写入'\0', 最多输出len - 1个字节
+    let cap = bytes.len().saturating_sub(1);
+    let truncated = usize::try_from(ret).map_or(true, |ret| ret > cap);
+    let mut len = usize::try_from(ret).map_or(0, |ret| ret.min(cap));
+    let mut pos = 0;
+    while let Err(e) = core::str::from_utf8(&bytes[pos..len]) {
+        pos += e.valid_up_to();
+        match e.error_len() {
+            Some(n) => bytes[pos..pos + n].fill(b'?'),
+            None if truncated => len = pos,
+            None => bytes[pos..len].fill(b'?'),
+        }
+    }
+    bytes[len..].fill(0);
+    (len, truncated)
+}
+
+/// `bprint_slice!`的返回值: buf中写入的部分(不包括末尾的'\0')以及输出是否被截断
+pub fn bytes_written(buf: &mut [u8], ret: i32) -> (&[u8], bool) {
+    let cap = buf.len().saturating_sub(1);
+    match usize::try_from(ret) {
+        Ok(ret) => (&buf[..ret.min(cap)], ret > cap),
+        Err(_) => (&[], true),
+    }
+}
+
+/// `sprint_str!`的返回值: 同`sprint!`一样保证合法的UTF-8, 返回写入的部分以及输出是否被截断
+pub fn str_written(buf: &mut str, ret: i32) -> (&str, bool) {
+    let (len, truncated) = utf8_fixup(buf, ret);
+    (&buf[..len], truncated)
+}
+
+/// `format!`/`format_bytes!`计算长度之后分配的buf. libc模式下snprintf还要写入末尾的'\0',
+/// nolibc模式下由`Vec<u8>`的`Formatter`追加, 只预留容量.
+#[cfg(feature = "alloc")]
+pub fn alloc_buf(len: i32) -> alloc::vec::Vec<u8> {
+    let len = usize::try_from(len).unwrap_or(0);
+    if cfg!(feature = "nolibc") {
+        alloc::vec::Vec::with_capacity(len)
+    } else {
+        alloc::vec![0; len + 1]
+    }
+}
+
+/// `format_bytes!`的返回值: 去掉libc模式下末尾的'\0', 出错时返回空的`Vec`
+#[cfg(feature = "alloc")]
+pub fn into_bytes(mut buf: alloc::vec::Vec<u8>, len: i32) -> alloc::vec::Vec<u8> {
+    buf.truncate(usize::try_from(len).unwrap_or(0));
+    buf
+}
+
+/// `format!`的返回值: `{:rb}`输出的非UTF-8字节替换为U+FFFD
+#[cfg(feature = "alloc")]
+pub fn into_string(buf: alloc::vec::Vec<u8>, len: i32) -> alloc::string::String {
+    match alloc::string::String::from_utf8(into_bytes(buf, len)) {
+        Ok(s) => s,
+        Err(e) => alloc::string::String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+/// `Args::dprintf`转发的目标, 同`dprintf`
+#[cfg(not(feature = "nolibc"))]
+pub type DprintfFn = unsafe extern "C" fn(i32, *const u8, ...) -> i32;
+/// `Args::snprintf`转发的目标, 同`snprintf`
+#[cfg(not(feature = "nolibc"))]
+pub type SnprintfFn = unsafe extern "C" fn(*mut u8, usize, *const u8, ...) -> i32;
+
+/// libc模式下`args!`生成的闭包的输出目标
+#[cfg(not(feature = "nolibc"))]
+pub enum Sink<'a> {
+    Fd(DprintfFn, i32),
+    Buf(SnprintfFn, &'a mut [u8]),
+}
+
+/// `write!`/`writeln!`的输出目标: libc模式下为实现了`Append`的类型, nolibc模式下为任意`Formatter`.
+/// 宏通过方法调用取得`&mut`引用, 参数可以是值, `&mut`引用或者`&mut`临时值.
+pub trait WriteTarget {
+    fn write_target(&mut self) -> &mut Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "nolibc"))]
+impl<T: Append + ?Sized> WriteTarget for T {}
+#[cfg(feature = "nolibc")]
+impl<T: Formatter + ?Sized> WriteTarget for T {}
+
+/// `print!`等宏一次完整的输出, 参数为注册的全局formatter, 通过它使用`Formatter`的全部接口
+#[cfg(feature = "nolibc")]
+pub type GlobalPrint<'a> = &'a mut dyn FnMut(&mut dyn Formatter) -> i32;
+
+#[cfg(feature = "nolibc")]
+extern "Rust" {
+    // 由`make_nolibc_formatter!`或者`nolibc_formatter!`在二进制中的任意crate定义一次,
+    // 链接时报告找不到这个符号说明没有注册, 重复定义说明注册了多次
+    fn _hifmt_register_with_make_nolibc_formatter(fd: i32, print: GlobalPrint<'_>) -> i32;
+}
+
+/// nolibc模式下`print!`等宏通过链接时注册的全局formatter输出, 不需要在调用处引入任何类型.
+/// 没有使用时不生成代码, 不会引用注册的符号.
+#[cfg(feature = "nolibc")]
+#[inline]
+pub fn print(fd: i32, print: GlobalPrint<'_>) -> i32 {
+    // SAFETY: 注册宏按相同的签名定义该符号
+    unsafe { _hifmt_register_with_make_nolibc_formatter(fd, print) }
+}
+
+/// libc模式下`write!`的输出目标, 由`::hifmt::Cursor`和`::hifmt::ArrayString`实现.
+/// snprintf输出到`spare`返回的buf, 再根据返回值移动写入位置.
+#[cfg(not(feature = "nolibc"))]
+pub trait Append {
+    fn spare(&mut self) -> &mut [u8];
+    fn advance(&mut self, ret: i32) -> i32;
+}
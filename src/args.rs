@@ -0,0 +1,48 @@
+//! `args!`的返回值: 保存一次格式化输出的参数, 用于编写自己的日志等输出函数, 类似`core::fmt::Arguments`.
+
+#[cfg(not(feature = "nolibc"))]
+use crate::arg::{DprintfFn, Sink, SnprintfFn};
+#[cfg(feature = "nolibc")]
+use crate::Formatter;
+
+/// 由`args!`创建, 参数已经求值, 输出时才转换, 可以复制并多次输出.
+/// 同`format_args!`一样引用了语句中的临时值, 只能直接作为函数参数使用.
+#[derive(Clone, Copy)]
+pub struct Args<'a> {
+    #[cfg(not(feature = "nolibc"))]
+    write: &'a dyn Fn(Sink<'_>) -> i32,
+    #[cfg(feature = "nolibc")]
+    write: &'a dyn Fn(&mut dyn Formatter) -> usize,
+}
+
+impl<'a> Args<'a> {
+    #[doc(hidden)]
+    #[cfg(not(feature = "nolibc"))]
+    pub fn new(write: &'a dyn Fn(Sink<'_>) -> i32) -> Self {
+        Self { write }
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "nolibc")]
+    pub fn new(write: &'a dyn Fn(&mut dyn Formatter) -> usize) -> Self {
+        Self { write }
+    }
+
+    /// 转发给`dprintf`形式的函数, 例如`args.dprintf(dprintf, 2)`, 返回值同`print!`
+    #[cfg(not(feature = "nolibc"))]
+    pub fn dprintf(self, f: DprintfFn, fd: i32) -> i32 {
+        (self.write)(Sink::Fd(f, fd))
+    }
+
+    /// 转发给`snprintf`形式的函数, 输出到buf并写入'\0', 返回值同`bprint!`
+    #[cfg(not(feature = "nolibc"))]
+    pub fn snprintf(self, f: SnprintfFn, buf: &mut [u8]) -> i32 {
+        (self.write)(Sink::Buf(f, buf))
+    }
+
+    /// 输出到`Formatter`, 返回值为完整输出的长度
+    #[cfg(feature = "nolibc")]
+    pub fn write_to(self, f: &mut impl Formatter) -> usize {
+        (self.write)(f)
+    }
+}
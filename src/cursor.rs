@@ -0,0 +1,206 @@
+//! `write!`的输出目标: 在buf中依次追加输出, 便于分多次或者在循环中拼接一条消息.
+
+use core::ffi::CStr;
+
+#[cfg(not(feature = "nolibc"))]
+use crate::arg::Append;
+#[cfg(feature = "nolibc")]
+use crate::Formatter;
+
+/// `Cursor`和`ArrayString`共用的写入位置. 同snprintf一样在已写入内容之后保留并写入'\0',
+/// 某次输出放不下时截断并记录下来.
+#[derive(Clone, Copy, Default)]
+struct Pos {
+    pos: usize,
+    truncated: bool,
+}
+
+impl Pos {
+    fn start(buf: &mut [u8]) -> Self {
+        if let Some(nul) = buf.first_mut() {
+            *nul = 0;
+        }
+        Self::default()
+    }
+
+    fn remaining(&self, buf: &[u8]) -> usize {
+        buf.len().saturating_sub(1) - self.pos
+    }
+
+    #[cfg(feature = "nolibc")]
+    fn append(&mut self, buf: &mut [u8], data: &[u8]) -> usize {
+        let len = data.len().min(self.remaining(buf));
+        buf[self.pos..self.pos + len].copy_from_slice(&data[..len]);
+        self.pos += len;
+        if let Some(nul) = buf.get_mut(self.pos) {
+            *nul = 0;
+        }
+        self.truncated |= len < data.len();
+        data.len()
+    }
+
+    /// libc模式下根据snprintf的返回值移动位置
+    #[cfg(not(feature = "nolibc"))]
+    fn advance(&mut self, buf: &[u8], ret: i32) -> i32 {
+        let remaining = self.remaining(buf);
+        match usize::try_from(ret) {
+            Ok(len) => {
+                self.pos += len.min(remaining);
+                self.truncated |= len > remaining;
+            }
+            Err(_) => self.truncated = true,
+        }
+        ret
+    }
+}
+
+/// 在借用的buf中追加输出的游标.
+pub struct Cursor<'a> {
+    buf: &'a mut [u8],
+    pos: Pos,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        let pos = Pos::start(buf);
+        Self { buf, pos }
+    }
+
+    /// 已经写入的字节数, 不包括末尾的'\0'
+    pub fn pos(&self) -> usize {
+        self.pos.pos
+    }
+
+    /// 还能写入的字节数, 不包括末尾的'\0'
+    pub fn remaining(&self) -> usize {
+        self.pos.remaining(self.buf)
+    }
+
+    /// 是否有输出被截断
+    pub fn is_truncated(&self) -> bool {
+        self.pos.truncated
+    }
+
+    /// 已经写入的内容, 不包括末尾的'\0'
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.pos.pos]
+    }
+
+    /// 已经写入的内容, `{:rb}`或者截断可能导致不是合法的UTF-8
+    pub fn as_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
+
+    /// 清空已经写入的内容以及截断标记
+    pub fn clear(&mut self) {
+        self.pos = Pos::start(self.buf);
+    }
+}
+
+#[cfg(not(feature = "nolibc"))]
+impl Append for Cursor<'_> {
+    fn spare(&mut self) -> &mut [u8] {
+        &mut self.buf[self.pos.pos..]
+    }
+    fn advance(&mut self, ret: i32) -> i32 {
+        self.pos.advance(self.buf, ret)
+    }
+}
+
+#[cfg(feature = "nolibc")]
+impl Formatter for Cursor<'_> {
+    fn new(_fd: i32) -> Self {
+        Self {
+            buf: &mut [],
+            pos: Pos::default(),
+        }
+    }
+    fn write_buf(&mut self, buf: &[u8]) -> usize {
+        self.pos.append(self.buf, buf)
+    }
+}
+
+/// 定长的字符串, 不需要`alloc`就可以把格式化的消息作为值保存或者返回.
+/// 最多保存`N - 1`个字节, 末尾总是'\0'; 由`write!`追加输出, 或者由`format_array!`创建.
+#[derive(Clone, Copy)]
+pub struct ArrayString<const N: usize> {
+    buf: [u8; N],
+    pos: Pos,
+}
+
+impl<const N: usize> ArrayString<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            pos: Pos {
+                pos: 0,
+                truncated: false,
+            },
+        }
+    }
+
+    /// 已经写入的字节数, 不包括末尾的'\0'
+    pub fn len(&self) -> usize {
+        self.pos.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos.pos == 0
+    }
+
+    /// 还能写入的字节数, 不包括末尾的'\0'
+    pub fn remaining(&self) -> usize {
+        self.pos.remaining(&self.buf)
+    }
+
+    /// 是否有输出被截断
+    pub fn is_truncated(&self) -> bool {
+        self.pos.truncated
+    }
+
+    /// 已经写入的内容, 不包括末尾的'\0'
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.pos.pos]
+    }
+
+    /// 已经写入的内容, `{:rb}`或者截断可能导致不是合法的UTF-8
+    pub fn as_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
+
+    /// 以'\0'结尾的C字符串, 内容中含有'\0'时截止到第一个'\0'; `N`为0时为空字符串
+    pub fn as_cstr(&self) -> &CStr {
+        CStr::from_bytes_until_nul(&self.buf).unwrap_or_default()
+    }
+
+    /// 清空已经写入的内容以及截断标记
+    pub fn clear(&mut self) {
+        self.pos = Pos::start(&mut self.buf);
+    }
+}
+
+impl<const N: usize> Default for ArrayString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(feature = "nolibc"))]
+impl<const N: usize> Append for ArrayString<N> {
+    fn spare(&mut self) -> &mut [u8] {
+        &mut self.buf[self.pos.pos..]
+    }
+    fn advance(&mut self, ret: i32) -> i32 {
+        self.pos.advance(&self.buf, ret)
+    }
+}
+
+#[cfg(feature = "nolibc")]
+impl<const N: usize> Formatter for ArrayString<N> {
+    fn new(_fd: i32) -> Self {
+        Self::new()
+    }
+    fn write_buf(&mut self, buf: &[u8]) -> usize {
+        self.pos.append(&mut self.buf, buf)
+    }
+}
@@ -22,16 +22,27 @@ fn main() {
     let b = &mut [0_u8; 100];
     hifmt::bprint!(b, "bprint({:rs})", "hello snprintf");
 
+    #[cfg(not(feature = "strict"))]
+    let u = 200;
+    #[cfg(feature = "strict")]
+    let u = 200_u32;
     hifmt::println!(
-        "d = {:d} u = {:u} x = {:x} e = {:e} p = {:p} cstr = {:cs} str = {:rs} bytes = {:rb} char = {:rc}",
+        "d = {:d} u = {:u} x = {:x} p = {:p} cstr = {:cs} str = {:rs} bytes = {:rb} char = {:rc}",
         100,
-        200,
+        u,
         300,
-        400.0,
         b,
         b,
         s,
         b,
         '中'
     );
+    #[cfg(not(feature = "no-float"))]
+    {
+        #[cfg(not(all(feature = "strict", feature = "nolibc", feature = "f32")))]
+        let f = 400.0;
+        #[cfg(all(feature = "strict", feature = "nolibc", feature = "f32"))]
+        let f = 400.0_f32;
+        hifmt::println!("e = {:e} f = {:.2f} g = {:g} a = {:a}", f, f, f, f);
+    }
 }
@@ -23,15 +23,22 @@
 //!
 //! hifmt::println!("hello world");
 //! hifmt::println!("signed decimal {:d}", -1);
+//! #[cfg(not(feature = "strict"))]
 //! hifmt::println!("unsigned decimal {:u}", -1);
 //! hifmt::println!("hexadecimal {:x}", -1);
 //! hifmt::println!("pointer {:p}", &1);
-//! hifmt::println!("float {:e}", -1.0);
+//! #[cfg(not(any(feature = "no-float", all(feature = "strict", feature = "nolibc", feature = "f32"))))]
+//! hifmt::println!("float {:e} {:.2f} {:g} {:a}", -1.0, 3.14159, 0.0001, 0.1);
 //! hifmt::println!("rust &str {:rs}", "hello world");
 //! hifmt::println!("rust &[u8] {:rb}", b"hello world");
 //! hifmt::println!("rust char {:rc}", '中');
 //! hifmt::println!("c str {:cs}", b"hello world\0");
 //! hifmt::println!("c char {:cc}", b'0');
+//! hifmt::println!("zero padded hex {:#010x}", 0xbeef);
+//! hifmt::println!("aligned [{:<8rs}] [{:>8rs}]", "left", "right");
+//! hifmt::println!("runtime width [{:*d}]", 6, -42);
+//! let count = 3;
+//! hifmt::println!("{0:d} = {0:#x}, {name:rs}: {count:d}", 255, name = "items");
 //!
 //! let mut buf = [0_u8; 100];
 //! hifmt::bprint!(&mut buf, "snprintf rust string {:rs}", "hello world");
@@ -45,6 +52,11 @@
 //! assert_eq!(len as usize, s.len());
 //!
 //!
+//! // test_nul_terminated
+//! let mut buf = [b'#'; 6];
+//! let len = hifmt::bprint!(&mut buf, "{:rs}", s);
+//! assert_eq!((len, &buf), (11, b"hello\0"));
+//!
 //! // test_fat_pointer
 //! let mut buf = [0_u8; 100];
 //! let s = "hello";
@@ -52,11 +64,392 @@
 //! hifmt::bprint!(&mut buf[0..], "{:p} {:p}", s, s);
 //! let s = format!("0x{:x} 0x{:x}\0", n, n);
 //! assert_eq!(s.as_bytes(), &buf[0..s.len()]);
+//!
+//! // test_eval_once
+//! let mut n = 0;
+//! let mut next = || {
+//!     n += 1;
+//!     n
+//! };
+//! hifmt::println!("{:d} {:d}", next(), next());
+//! assert_eq!(n, 2);
+//!
+//! // test_percent_literal
+//! let mut buf = [0_u8; 16];
+//! let len = hifmt::bprint!(&mut buf, "load {:d}%", 100);
+//! assert_eq!(&buf[..len as usize], b"load 100%");
+//!
+//! // test_spec
+//! let mut buf = [0_u8; 32];
+//! let len = hifmt::bprint!(&mut buf, "[{:08x}] [{:+d}] [{:>5rs}] [{:.2rs}]", 0xbeef, 7, "ab", "abc");
+//! assert_eq!(&buf[..len as usize], b"[0000beef] [+7] [   ab] [ab]");
+//!
+//! // test_named_args
+//! let mut buf = [0_u8; 32];
+//! let width = 4;
+//! let len = hifmt::bprint!(&mut buf, "{2:d} {2:x} {s:rs} {width:d} {:*d}", width, 1, 10, s = "s");
+//! assert_eq!(&buf[..len as usize], b"10 a s 4    1");
+//! // `*`参数超出i32范围时取i32::MAX, 不会回绕成1
+//! #[cfg(target_pointer_width = "64")]
+//! {
+//!     let len = hifmt::bprint!(&mut buf, "[{:.*rs}]", (1_usize << 32) + 1, "abc");
+//!     assert_eq!(&buf[..len as usize], b"[abc]");
+//! }
+//!
+//! // test_int_width
+//! let mut buf = [0_u8; 64];
+//! #[cfg(not(feature = "strict"))]
+//! let len = hifmt::bprint!(&mut buf, "{:x} {:x} {:u} {:d}", -1, -1_i64, -1_i8, u32::MAX);
+//! #[cfg(feature = "strict")]
+//! let len = hifmt::bprint!(&mut buf, "{:x} {:x} {:u} {:d}", -1, -1_i64, 255_u8, u32::MAX);
+//! assert_eq!(&buf[..len as usize], b"ffffffff ffffffffffffffff 255 4294967295");
+//! let len = hifmt::bprint!(&mut buf, "{:x} {:#06x} {:d} {:b}", -2_i8, i16::MIN, -2_i8, -1_i8);
+//! assert_eq!(&buf[..len as usize], b"fe 0x8000 -2 11111111");
+//!
+//! // test_hiarg
+//! use core::num::{NonZeroU8, Wrapping};
+//! use core::sync::atomic::AtomicU32;
+//! struct Pid(u32);
+//! impl hifmt::HiArg for Pid {
+//!     type Prim = u32;
+//!     fn hi_arg(&self) -> u32 {
+//!         self.0
+//!     }
+//! }
+//! let mut buf = [0_u8; 64];
+//! let (pid, count) = (Pid(42), AtomicU32::new(7));
+//! #[cfg(not(feature = "strict"))]
+//! {
+//!     let len = hifmt::bprint!(&mut buf, "{pid:d} {pid:#x} {:d} {:u} {:d}", &count, Wrapping(-1_i8), NonZeroU8::new(3).unwrap());
+//!     assert_eq!(&buf[..len as usize], b"42 0x2a 7 255 3");
+//! }
+//! let len = hifmt::bprint!(&mut buf, "{pid:d} {pid:#x} {:d} {:x} {:d}", &count, Wrapping(-1_i8), NonZeroU8::new(3).unwrap());
+//! assert_eq!(&buf[..len as usize], b"42 0x2a 7 ff 3");
+//!
+//! // test_radix
+//! let mut buf = [0_u8; 64];
+//! let len = hifmt::bprint!(&mut buf, "{:o} {:#o} {:X} {:#06X} {:b} {:#010b}", 8, 8, 255, 255, 5, 5);
+//! assert_eq!(&buf[..len as usize], b"10 0o10 FF 0x00FF 101 0b00000101");
+//! let len = hifmt::bprint!(&mut buf, "[{:#12.5b}|{:<#10.5b}|{:#.0o}]", 5, 5, 0);
+//! assert_eq!(&buf[..len as usize], b"[     0b00101|0b00101   |]");
+//! // 补0不占用栈上的缓冲区, 宽度再大也只需要固定大小的缓冲区
+//! let len = hifmt::bprint!(&mut buf, "{:0100000b}", 5);
+//! assert_eq!((len, &buf[60..63]), (100000, &b"000"[..]));
+//!
+//! // test_lossy: feature = "strict"时这些有损转换编译报错
+//! #[cfg(not(feature = "strict"))]
+//! {
+//!     let len = hifmt::bprint!(&mut buf, "{:d} {:d} {:u} {:u}", 3.7, true, -1_i8, -1_i64);
+//!     assert_eq!(&buf[..len as usize], b"3 1 255 18446744073709551615");
+//! }
+//!
+//! // test_int128
+//! let mut buf = [0_u8; 128];
+//! let len = hifmt::bprint!(&mut buf, "{:d128} {:u128}", i128::MIN, u128::MAX);
+//! assert_eq!(&buf[..len as usize], b"-170141183460469231731687303715884105728 340282366920938463463374607431768211455");
+//! #[cfg(not(feature = "strict"))]
+//! let len = hifmt::bprint!(&mut buf, "{:#x128} {:+d128}|{:<6u128}|", 1_u128 << 100, 7, 42);
+//! #[cfg(feature = "strict")]
+//! let len = hifmt::bprint!(&mut buf, "{:#x128} {:+d128}|{:<6u128}|", 1_u128 << 100, 7, 42_u32);
+//! assert_eq!(&buf[..len as usize], b"0x10000000000000000000000000 +7|42    |");
+//!
+//! // test_float: feature = "strict"且nolibc+f32时浮点字面量需要`_f32`后缀
+//! #[cfg(not(any(feature = "no-float", all(feature = "strict", feature = "nolibc", feature = "f32"))))]
+//! {
+//!     let mut buf = [0_u8; 64];
+//!     let len = hifmt::bprint!(&mut buf, "{:e} {:.3f} {:g} {:+08.1f}", 1234.5, 2.0006, 1e-5, -0.25);
+//!     assert_eq!(&buf[..len as usize], b"1.234500e+03 2.001 1e-05 -00000.2");
+//!     let len = hifmt::bprint!(&mut buf, "{:a} {:a} {:.1a}", 1.5, -0.375, 1.96875);
+//!     assert_eq!(&buf[..len as usize], b"0x1.8p+0 -0x1.8p-2 0x2.0p+0");
+//! }
+//! // test_cstr
+//! let c = core::ffi::CStr::from_bytes_with_nul(b"cstr\0").unwrap();
+//! let arr = *b"nonul";
+//! let mut buf = [0_u8; 32];
+//! let len = hifmt::bprint!(&mut buf, "{:cs} {:cs} {:.2cs} {:cs} {:cs}", c, &arr, c, &arr[1..3], b"lit\0");
+//! assert_eq!(&buf[..len as usize], b"cstr nonul cs on lit");
+//! // test_bprint_slice
+//! let mut buf = [0_u8; 8];
+//! let (out, truncated) = hifmt::bprint_slice!(&mut buf, "{:d}-{:rs}", 42, "ok");
+//! assert_eq!((out, truncated), (&b"42-ok"[..], false));
+//! let (out, truncated) = hifmt::bprint_slice!(&mut buf, "{:rs}", "hello world");
+//! assert_eq!((out, truncated), (&b"hello w"[..], true));
+//! let mut s = String::from("......");
+//! let (out, truncated) = hifmt::sprint_str!(&mut s, "a{:rs}", "中文");
+//! assert_eq!((out, truncated), ("a中", true));
+//! // test_cursor
+//! let mut buf = [b'#'; 16];
+//! let mut cursor = hifmt::Cursor::new(&mut buf);
+//! for x in [1, 22, 333] {
+//!     hifmt::write!(cursor, "{:d},", x);
+//! }
+//! assert_eq!((cursor.as_bytes(), cursor.remaining()), (&b"1,22,333,"[..], 6));
+//! let c = &mut cursor;
+//! assert_eq!(hifmt::write!(c, "{:rs}", "truncated"), 9);
+//! assert_eq!((cursor.as_str(), cursor.is_truncated()), (Ok("1,22,333,trunca"), true));
+//! assert_eq!(buf[15], 0);
+//! // test_write_formatter
+//! fn line(c: &mut hifmt::Cursor, n: i32) {
+//!     hifmt::writeln!(c, "n={:d}", n);
+//! }
+//! let mut buf = [0_u8; 16];
+//! let mut cursor = hifmt::Cursor::new(&mut buf);
+//! line(&mut cursor, 1);
+//! hifmt::write!(&mut cursor, "{:#x}", 255);
+//! assert_eq!(cursor.as_str(), Ok("n=1\n0xff"));
+//! #[cfg(feature = "nolibc")]
+//! {
+//!     struct Capture<'a>(&'a mut [u8], usize);
+//!     impl hifmt::Formatter for Capture<'_> {
+//!         fn new(_fd: i32) -> Self {
+//!             Capture(&mut [], 0)
+//!         }
+//!         fn write_buf(&mut self, buf: &[u8]) -> usize {
+//!             self.0[self.1..self.1 + buf.len()].copy_from_slice(buf);
+//!             self.1 += buf.len();
+//!             buf.len()
+//!         }
+//!     }
+//!     let mut out = [0_u8; 16];
+//!     assert_eq!(hifmt::writeln!(&mut Capture(&mut out, 0), "rx {:u}", 42_u8), 6);
+//!     assert_eq!(&out[..6], b"rx 42\n");
+//!     let mut capture = Capture(&mut out, 0);
+//!     hifmt::write!(capture, "{:rs}", "ok");
+//!     assert_eq!(capture.1, 2);
+//! }
+//! // test_array_string
+//! fn describe(id: u32) -> hifmt::ArrayString<16> {
+//!     hifmt::format_array!("dev{:u}: {:rs}", id, "ready")
+//! }
+//! let s = describe(7);
+//! assert_eq!((s.as_str(), s.is_truncated()), (Ok("dev7: ready"), false));
+//! assert_eq!(s.as_cstr().to_bytes(), b"dev7: ready");
+//! let mut s = hifmt::format_array!(8, "{:x}", 0xabcd_u32);
+//! hifmt::write!(s, "{:rs}", "-long");
+//! assert_eq!((s.as_bytes(), s.is_truncated()), (&b"abcd-lo"[..], true));
+//! // test_alloc_format
+//! #[cfg(feature = "alloc")]
+//! {
+//!     let mut calls = 0;
+//!     let mut next = || { calls += 1; calls };
+//!     let s: String = hifmt::format!("[{:d}] {:>6rs} {:rb}", next(), "中", b"\xffz");
+//!     assert_eq!((s.as_str(), calls), ("[1]    中 \u{fffd}z", 1));
+//!     let v = hifmt::format_bytes!("{:rb}{:#x}", b"a\0", 255);
+//!     assert_eq!(v, b"a\00xff");
+//!     assert!(hifmt::format!("").is_empty());
+//! }
+//! // test_args
+//! fn log(buf: &mut [u8], level: i32, args: hifmt::Args) -> i32 {
+//!     let pos = hifmt::bprint!(&mut *buf, "<{:d}> ", level) as usize;
+//!     let buf = &mut buf[pos..];
+//!     #[cfg(not(feature = "nolibc"))]
+//!     let len = args.snprintf(snprintf, buf);
+//!     #[cfg(feature = "nolibc")]
+//!     let len = args.write_to(&mut hifmt::BufFormatter::from_slice(buf)) as i32;
+//!     len
+//! }
+//! let mut calls = 0;
+//! let mut next = || { calls += 1; calls };
+//! let mut buf = [0_u8; 32];
+//! let dev = ("dev", 0xff);
+//! assert_eq!(log(&mut buf, 3, hifmt::args!("{:rs}{:d}: {:#x}", dev.0, next(), dev.1)), 10);
+//! assert_eq!(&buf[..15], b"<3> dev1: 0xff\0");
+//! assert_eq!(calls, 1);
+//! // test_sprint_utf8
+//! let mut s = String::from("......");
+//! let (len, truncated) = hifmt::sprint!(&mut s, "a{:rs}", "中文");
+//! assert_eq!((len, truncated), (4, true));
+//! assert_eq!(s, "a中\0\0");
+//! // `{:rb}`中的非法字节替换为`?`, 没有超出buf时不算截断
+//! let mut s = String::from("abcdef");
+//! let (len, truncated) = hifmt::sprint!(&mut s, "{:rb}", b"\xffa\xfe");
+//! assert_eq!((len, truncated, s.as_str()), (3, false, "?a?\0\0\0"));
+//! // test_nul_bytes
+//! let pkt = b"a\0b\0";
+//! let mut buf = [0_u8; 32];
+//! let len = hifmt::bprint!(&mut buf, "[{:rb}] [{:>6rb}] [{:.3rs}] {:d}", pkt, pkt, "x\0yz", 7);
+//! assert_eq!(&buf[..len as usize], b"[a\0b\0] [  a\0b\0] [x\0y] 7");
+//! let len = hifmt::bprint!(&mut buf, "{:*rb}|{:x} 100% {:#b}", -3, &pkt[..2], -1_i64, 5);
+//! assert_eq!(&buf[..len as usize], b"a\0 |ffffffffffffffff 100% 0b101");
+//! ```
+//!
+//! With `nolibc`, the registered formatter is used through the whole `Formatter` interface, so its overrides apply:
+//!
+#![cfg_attr(feature = "nolibc", doc = "```rust")]
+#![cfg_attr(not(feature = "nolibc"), doc = "```ignore")]
+//! struct Printer;
+//! impl hifmt::Formatter for Printer {
+//!     fn new(_fd: i32) -> Self {
+//!         Printer
+//!     }
+//!     fn write_buf(&mut self, buf: &[u8]) -> usize {
+//!         buf.len()
+//!     }
+//!     fn write_u32(&mut self, _val: u32) -> usize {
+//!         self.write_buf(b"<u32>")
+//!     }
+//! }
+//! hifmt::nolibc_formatter!(Printer);
+//!
+//! assert_eq!(hifmt::print!("{:u}", 7_u32), 5);
+//! assert_eq!(hifmt::println!("{:d}", 7), 2);
+//! ```
+//!
+//! # Compile errors
+//!
+//! The format string and the arguments are checked at compile time. Every argument must be used:
+//!
+//! ```compile_fail
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:d}", 1, 2);
+//! ```
+//!
+//! Named arguments must be unique:
+//!
+//! ```compile_fail
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{a:d}", a = 1, a = 2);
+//! ```
+//!
+//! Positional arguments must come before named ones:
+//!
+//! ```compile_fail
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{a:d} {:d}", a = 1, 2);
+//! ```
+//!
+//! A byte string literal passed to `{:cs}` must end with its only `\0`:
+//!
+//! ```compile_fail
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:cs}", b"abc");
+//! ```
+//!
+//! ```compile_fail
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:cs}", b"a\0b\0");
+//! ```
+//!
+//! With `features = ["no-float"]` every float specifier is rejected:
+//!
+#![cfg_attr(feature = "no-float", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "no-float"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:e}", 1.0);
+//! ```
+//!
+#![cfg_attr(feature = "no-float", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "no-float"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:f}", 1.0);
+//! ```
+//!
+//! With `features = ["strict"]` lossy conversions are rejected at the argument:
+//!
+#![cfg_attr(feature = "strict", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "strict"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:d}", 1.5_f64);
+//! ```
+//!
+#![cfg_attr(feature = "strict", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "strict"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:d}", true);
+//! ```
+//!
+#![cfg_attr(feature = "strict", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "strict"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:d}", 1_u64);
+//! ```
+//!
+#![cfg_attr(feature = "strict", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "strict"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:u}", 1_i64);
+//! ```
+//!
+//! and so are `*` widths and precisions other than `i32` and `usize`:
+//!
+#![cfg_attr(feature = "strict", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "strict"), doc = "```ignore")]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:*d}", 8_u64, 1);
+//! ```
+//!
+//! With `features = ["strict", "nolibc", "f32"]` an `f64` would be narrowed to `f32`:
+//!
+#![cfg_attr(
+    all(feature = "strict", feature = "nolibc", feature = "f32"),
+    doc = "```compile_fail"
+)]
+#![cfg_attr(
+    not(all(feature = "strict", feature = "nolibc", feature = "f32")),
+    doc = "```ignore"
+)]
+//! # #[cfg(not(feature = "nolibc"))]
+//! # extern "C" {
+//! #     fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+//! # }
+//! let mut buf = [0_u8; 32];
+//! hifmt::bprint!(&mut buf, "{:f}", 1.5_f64);
 //! ```
 //!
 
 //#![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[cfg(not(feature = "nolibc"))]
 mod libc;
 #[cfg(not(feature = "nolibc"))]
@@ -67,6 +460,16 @@ mod nolibc;
 #[cfg(feature = "nolibc")]
 pub use nolibc::*;
 
+mod args;
+pub use args::Args;
+
+mod cursor;
+pub use cursor::{ArrayString, Cursor};
+
+#[doc(hidden)]
+pub mod arg;
+pub use arg::HiArg;
+
 #[inline(never)]
 pub fn encode_utf8(c: char, buf: &mut [u8; 4]) -> &[u8] {
     let mut u = c as u32;
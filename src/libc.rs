@@ -1,4 +1,31 @@
 pub use hifmt_macros::{
-    bprint, cbprint, ceprint, ceprintln, cprint, cprintln, csprint, eprint, eprintln, print,
-    println, sprint,
+    args, bprint, bprint_slice, cbprint, ceprint, ceprintln, cprint, cprintln, csprint, eprint,
+    eprintln, format_array, print, println, sprint, sprint_str, write, writeln,
 };
+#[cfg(feature = "alloc")]
+pub use hifmt_macros::{format_bytes, format_string as format};
+
+/// 将模板中的0xFF依次替换为整数参数的长度修饰符, 生成最终的printf格式字符串.
+/// `hifmt`宏在常量上下文中调用, 多余的空间填充为0.
+#[doc(hidden)]
+pub const fn cfmt<const N: usize>(template: &[u8], lens: &[&[u8]]) -> [u8; N] {
+    let mut buf = [0_u8; N];
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    while i < template.len() {
+        if template[i] == 0xFF {
+            let len = lens[k];
+            let mut l = 0;
+            while l < len.len() {
+                buf[j] = len[l];
+                j += 1;
+                l += 1;
+            }
+            k += 1;
+        } else {
+            buf[j] = template[i];
+            j += 1;
+        }
+        i += 1;
+    }
+    buf
+}
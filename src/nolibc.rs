@@ -1,33 +1,115 @@
 /// feature = "nolibc"
 /// 用户必须提供一个字符串输出函数: fn(&[u8]) -> usize
-/// 这里将此输出函数适配到`hifmt::Formatter`用户`hifmt::print`系列, 适用于无多线程并发输出场景.
+/// 这里将此输出函数适配到`hifmt::Formatter`, 注册为`hifmt::print`系列的全局输出, 适用于无多线程并发输出场景.
+/// 在二进制中的任意模块或者crate调用一次即可, 链接时解析, 其他模块和库crate不需要引入任何类型.
 #[macro_export]
 macro_rules! make_nolibc_formatter {
     ($printf: ident) => {
-        #[allow(non_camel_case_types)]
-        struct _hifmt_Formatter;
-        impl $crate::Formatter for _hifmt_Formatter {
-            fn new(_: i32) -> Self {
-                _hifmt_Formatter
+        const _: () = {
+            #[allow(non_camel_case_types)]
+            struct _hifmt_Formatter;
+            impl $crate::Formatter for _hifmt_Formatter {
+                fn new(_: i32) -> Self {
+                    _hifmt_Formatter
+                }
+                fn write_buf(&mut self, buf: &[u8]) -> usize {
+                    $printf(buf)
+                }
             }
-            fn write_buf(&mut self, buf: &[u8]) -> usize {
-                $printf(buf)
+            #[no_mangle]
+            fn _hifmt_register_with_make_nolibc_formatter(
+                _fd: i32,
+                print: $crate::arg::GlobalPrint<'_>,
+            ) -> i32 {
+                print(&mut _hifmt_Formatter)
             }
-        }
+        };
     };
 }
 
 /// feature = "nolibc"
-/// 用户实现支持hifmt::Formater的类型用于`hifmt::print`系列.
-/// 如果打印输出有多线程同步需求，应该完整实现`Formatter`接口并接口`nolibc_formatter`使用.
+/// 用户实现支持hifmt::Formater的类型用于`hifmt::print`系列, 注册方式同`make_nolibc_formatter`.
+/// 如果打印输出有多线程同步需求，应该完整实现`Formatter`接口并接口`nolibc_formatter`使用:
+/// 每次打印创建一个实例, 整个打印期间有效, 打印时使用该类型实现的全部`Formatter`接口.
 #[macro_export]
 macro_rules! nolibc_formatter {
     ($formatter: path) => {
-        #[allow(non_camel_case_types)]
-        type _hifmt_Formatter = $formatter;
+        const _: () = {
+            #[no_mangle]
+            fn _hifmt_register_with_make_nolibc_formatter(
+                fd: i32,
+                print: $crate::arg::GlobalPrint<'_>,
+            ) -> i32 {
+                let mut formatter = <$formatter as $crate::Formatter>::new(fd);
+                print(&mut formatter)
+            }
+        };
     };
 }
 
+mod float;
+
+use crate::arg::{int_prefix, u128_buf};
+
+/// 对齐方式, 缺省与C的printf一致为右对齐
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// 格式说明`{:[[fill]align][+][#][0][width][.precision]type}`中的修饰部分,
+/// 由`hifmt`宏生成, 传给`Formatter::write_***_spec`系列接口.
+#[derive(Clone, Copy)]
+pub struct Spec {
+    pub fill: char,
+    pub align: Align,
+    pub sign: bool,
+    pub alternate: bool,
+    pub zero: bool,
+    pub width: usize,
+    pub precision: Option<usize>,
+}
+
+impl Spec {
+    /// 无任何修饰的`{:type}`
+    pub const DEFAULT: Spec = Spec {
+        fill: ' ',
+        align: Align::Right,
+        sign: false,
+        alternate: false,
+        zero: false,
+        width: 0,
+        precision: None,
+    };
+
+    /// `{:*d}`运行时宽度, 同printf一样负数代表左对齐
+    pub fn with_width(mut self, width: i32) -> Self {
+        if width < 0 {
+            self.align = Align::Left;
+        }
+        self.width = width.unsigned_abs() as usize;
+        self
+    }
+
+    /// `{:.*d}`运行时精度, 同printf一样负数视为未指定精度
+    pub fn with_precision(mut self, precision: i32) -> Self {
+        self.precision = if precision < 0 {
+            None
+        } else {
+            Some(precision as usize)
+        };
+        self
+    }
+
+    /// `{:cs}`最多读取`max`个字节, 例如字节数组的长度
+    pub fn with_max(mut self, max: usize) -> Self {
+        self.precision = Some(self.precision.map_or(max, |p| p.min(max)));
+        self
+    }
+}
+
 pub trait Formatter {
     /// fd = 1 代表标准输出端口
     /// fd = 2 代表错误输出端口
@@ -43,14 +125,43 @@ pub trait Formatter {
     fn write_i64(&mut self, val: i64) -> usize {
         self.write_buf(i64_buf(val, &mut [0_u8; 24]))
     }
+    /// 32位整数只使用32位运算, 适用于没有64位除法指令的平台
+    fn write_u32(&mut self, val: u32) -> usize {
+        self.write_buf(u32_buf(val, &mut [0_u8; 24]))
+    }
+    fn write_i32(&mut self, val: i32) -> usize {
+        self.write_buf(i32_buf(val, &mut [0_u8; 24]))
+    }
     fn write_hex(&mut self, val: u64) -> usize {
         self.write_buf(hex_buf(val, &mut [0_u8; 24]))
     }
+    /// `{:X}`
+    fn write_upper_hex(&mut self, val: u64) -> usize {
+        self.write_buf(radix_buf(val, b'X', &mut [0_u8; 64]))
+    }
+    /// `{:o}`
+    fn write_oct(&mut self, val: u64) -> usize {
+        self.write_buf(radix_buf(val, b'o', &mut [0_u8; 64]))
+    }
+    /// `{:b}`
+    fn write_bin(&mut self, val: u64) -> usize {
+        self.write_buf(radix_buf(val, b'b', &mut [0_u8; 64]))
+    }
+    /// `{:d128}`, `{:u128}`, `{:x128}`
+    fn write_u128(&mut self, val: u128) -> usize {
+        self.write_buf(u128_buf(val, b'u', &mut [0_u8; 128]))
+    }
+    fn write_i128(&mut self, val: i128) -> usize {
+        self.write_i128_spec(val, &Spec::DEFAULT)
+    }
+    fn write_hex128(&mut self, val: u128) -> usize {
+        self.write_buf(u128_buf(val, b'x', &mut [0_u8; 128]))
+    }
     fn write_ptr(&mut self, val: *const u8) -> usize {
         self.write_buf(ptr_buf(val, &mut [0_u8; 24]))
     }
     fn write_f64(&mut self, val: f64) -> usize {
-        self.write_buf(f64_buf(val, &mut [0_u8; 24]))
+        self.write_f64_exp(val, &Spec::DEFAULT)
     }
     /// # Safety
     /// 调用者保证是一个空指针或者有效的c字符串
@@ -69,11 +180,230 @@ pub trait Formatter {
             ))
         }
     }
+
+    /// 输出`n`个填充字符, 先在栈上填满一小段再分批输出, 避免逐个字符调用`write_buf`
+    #[inline(never)]
+    fn write_pad(&mut self, fill: char, n: usize) -> usize {
+        let mut buf = [0_u8; 4];
+        let fill = crate::encode_utf8(fill, &mut buf);
+        let mut chunk = [0_u8; 32];
+        let per_chunk = chunk.len() / fill.len();
+        for dst in chunk.chunks_exact_mut(fill.len()) {
+            dst.copy_from_slice(fill);
+        }
+        let (mut n, mut size) = (n, 0);
+        while n > 0 {
+            let count = n.min(per_chunk);
+            size += self.write_buf(&chunk[..count * fill.len()]);
+            n -= count;
+        }
+        size
+    }
+
+    /// 按`spec`的宽度和对齐方式输出一个字段: `[填充][prefix][zeros个0][body][填充]`,
+    /// prefix为符号或者`0x`之类的前缀, `0`标志的补零位于prefix之后.
+    #[inline(never)]
+    fn write_field(&mut self, prefix: &[u8], zeros: usize, body: &[u8], spec: &Spec) -> usize {
+        let (left, pad, right) = padding(prefix.len() + zeros + body.len(), spec);
+        let mut size = self.write_pad(spec.fill, left);
+        size += self.write_buf(prefix);
+        size += self.write_pad('0', zeros + pad);
+        size += self.write_buf(body);
+        size + self.write_pad(spec.fill, right)
+    }
+
+    fn write_buf_spec(&mut self, buf: &[u8], spec: &Spec) -> usize {
+        let len = spec.precision.map_or(buf.len(), |p| p.min(buf.len()));
+        self.write_field(b"", 0, &buf[..len], spec)
+    }
+    fn write_u64_spec(&mut self, val: u64, spec: &Spec) -> usize {
+        let mut buf = [0_u8; 24];
+        let digits = unsafe { u64_buf(val, &mut buf) };
+        write_int(self, b"", digits, val == 0, spec)
+    }
+    fn write_i64_spec(&mut self, val: i64, spec: &Spec) -> usize {
+        let mut buf = [0_u8; 24];
+        let digits = unsafe { u64_buf(val.unsigned_abs(), &mut buf) };
+        write_int(self, int_sign(val < 0, spec), digits, val == 0, spec)
+    }
+    fn write_u32_spec(&mut self, val: u32, spec: &Spec) -> usize {
+        let mut buf = [0_u8; 24];
+        let digits = u32_buf(val, &mut buf);
+        write_int(self, b"", digits, val == 0, spec)
+    }
+    fn write_i32_spec(&mut self, val: i32, spec: &Spec) -> usize {
+        let mut buf = [0_u8; 24];
+        let digits = u32_buf(val.unsigned_abs(), &mut buf);
+        write_int(self, int_sign(val < 0, spec), digits, val == 0, spec)
+    }
+    fn write_hex_spec(&mut self, val: u64, spec: &Spec) -> usize {
+        let mut buf = [0_u8; 24];
+        let digits = hex_buf(val, &mut buf);
+        let prefix: &[u8] = if spec.alternate && val != 0 {
+            b"0x"
+        } else {
+            b""
+        };
+        write_int(self, prefix, digits, val == 0, spec)
+    }
+    /// `#`输出`0x`前缀, 与`{:x}`一致
+    fn write_upper_hex_spec(&mut self, val: u64, spec: &Spec) -> usize {
+        write_radix64(self, val, b'X', spec)
+    }
+    /// `#`输出`0o`前缀
+    fn write_oct_spec(&mut self, val: u64, spec: &Spec) -> usize {
+        write_radix64(self, val, b'o', spec)
+    }
+    /// `#`输出`0b`前缀
+    fn write_bin_spec(&mut self, val: u64, spec: &Spec) -> usize {
+        write_radix64(self, val, b'b', spec)
+    }
+    fn write_u128_spec(&mut self, val: u128, spec: &Spec) -> usize {
+        write_radix(self, false, val, b'u', spec)
+    }
+    fn write_i128_spec(&mut self, val: i128, spec: &Spec) -> usize {
+        write_radix(self, val < 0, val.unsigned_abs(), b'd', spec)
+    }
+    fn write_hex128_spec(&mut self, val: u128, spec: &Spec) -> usize {
+        write_radix(self, false, val, b'x', spec)
+    }
+    fn write_ptr_spec(&mut self, val: *const u8, spec: &Spec) -> usize {
+        self.write_field(b"0x", 0, hex_buf(val as u64, &mut [0_u8; 24]), spec)
+    }
+    /// `{:e}`: d.dddddde+XX
+    fn write_f64_exp(&mut self, val: f64, spec: &Spec) -> usize {
+        float::write_f64(self, val, float::Style::Exp, spec)
+    }
+    /// `{:f}`: ddd.dddddd
+    fn write_f64_fixed(&mut self, val: f64, spec: &Spec) -> usize {
+        float::write_f64(self, val, float::Style::Fixed, spec)
+    }
+    /// `{:g}`: 根据指数选择`{:e}`或`{:f}`, 并去掉小数部分末尾的0
+    fn write_f64_general(&mut self, val: f64, spec: &Spec) -> usize {
+        float::write_f64(self, val, float::Style::General, spec)
+    }
+    /// `{:a}`: 0x1.hhhhp+d, 与glibc一致非规格化数输出为0x0.hhhhp-1022
+    fn write_f64_hex(&mut self, val: f64, spec: &Spec) -> usize {
+        float::write_f64(self, val, float::Style::Hex, spec)
+    }
+    /// feature = "f32"时的`{:e}`, 只使用整数运算
+    fn write_f32(&mut self, val: f32) -> usize {
+        self.write_f32_exp(val, &Spec::DEFAULT)
+    }
+    fn write_f32_exp(&mut self, val: f32, spec: &Spec) -> usize {
+        float::write_f32(self, val, float::Style::Exp, spec)
+    }
+    fn write_f32_fixed(&mut self, val: f32, spec: &Spec) -> usize {
+        float::write_f32(self, val, float::Style::Fixed, spec)
+    }
+    fn write_f32_general(&mut self, val: f32, spec: &Spec) -> usize {
+        float::write_f32(self, val, float::Style::General, spec)
+    }
+    /// 输出与`{:a}`打印提升为f64后的数值一致
+    fn write_f32_hex(&mut self, val: f32, spec: &Spec) -> usize {
+        float::write_f32(self, val, float::Style::Hex, spec)
+    }
+    /// # Safety
+    /// 调用者保证是一个空指针或者有效的c字符串, 指定精度时最多读取精度个字节
+    #[inline(never)]
+    unsafe fn write_cstr_spec(&mut self, val: *const u8, spec: &Spec) -> usize {
+        if val.is_null() {
+            return self.write_buf_spec(b"null", spec);
+        }
+        let max = spec.precision.unwrap_or(usize::MAX);
+        let mut len = 0;
+        while len < max && val.add(len).read() != 0 {
+            len += 1;
+        }
+        self.write_field(b"", 0, core::slice::from_raw_parts(val, len), spec)
+    }
+}
+
+/// 计算宽度不足时的填充: (左侧填充, prefix之后补0, 右侧填充)
+fn padding(len: usize, spec: &Spec) -> (usize, usize, usize) {
+    let pad = spec.width.saturating_sub(len);
+    if spec.zero && spec.align != Align::Left {
+        return (0, pad, 0);
+    }
+    match spec.align {
+        Align::Left => (0, 0, pad),
+        Align::Right => (pad, 0, 0),
+        Align::Center => (pad / 2, 0, pad - pad / 2),
+    }
+}
+
+fn int_sign(neg: bool, spec: &Spec) -> &'static [u8] {
+    if neg {
+        b"-"
+    } else if spec.sign {
+        b"+"
+    } else {
+        b""
+    }
+}
+
+/// 整数输出: 精度代表最少数字个数, 同printf一样指定精度时忽略`0`标志,
+/// 精度为0时数值0不输出任何数字.
+fn write_int<F: Formatter + ?Sized>(
+    f: &mut F,
+    prefix: &[u8],
+    digits: &[u8],
+    zero: bool,
+    spec: &Spec,
+) -> usize {
+    match spec.precision {
+        None => f.write_field(prefix, 0, digits, spec),
+        Some(precision) => {
+            let spec = Spec {
+                zero: false,
+                ..*spec
+            };
+            let digits = if precision == 0 && zero { b"" } else { digits };
+            f.write_field(
+                prefix,
+                precision.saturating_sub(digits.len()),
+                digits,
+                &spec,
+            )
+        }
+    }
+}
+
+/// 按格式字符(d/u, x/X, o, b)输出128位以内的整数
+#[inline(never)]
+fn write_radix64<F: Formatter + ?Sized>(f: &mut F, val: u64, ty: u8, spec: &Spec) -> usize {
+    let mut buf = [0_u8; 64];
+    let digits = radix_buf(val, ty, &mut buf);
+    let prefix = int_prefix(false, val != 0, ty, spec.sign, spec.alternate);
+    write_int(f, prefix, digits, val == 0, spec)
+}
+
+fn write_radix<F: Formatter + ?Sized>(
+    f: &mut F,
+    neg: bool,
+    val: u128,
+    ty: u8,
+    spec: &Spec,
+) -> usize {
+    let mut buf = [0_u8; 128];
+    let digits = u128_buf(val, ty, &mut buf);
+    let prefix = int_prefix(neg, val != 0, ty, spec.sign, spec.alternate);
+    write_int(f, prefix, digits, val == 0, spec)
 }
 
 #[inline(never)]
 fn i64_buf(val: i64, buf: &mut [u8; 24]) -> &[u8] {
-    let mut len = unsafe { u64_buf(val.abs() as u64, buf).len() };
+    let mut len = unsafe { u64_buf(val.unsigned_abs(), buf).len() };
+    if val < 0 {
+        buf[buf.len() - len - 1] = b'-';
+        len += 1;
+    }
+    &buf[buf.len() - len..]
+}
+
+#[inline(never)]
+fn i32_buf(val: i32, buf: &mut [u8; 24]) -> &[u8] {
+    let mut len = u32_buf(val.unsigned_abs(), buf).len();
     if val < 0 {
         buf[buf.len() - len - 1] = b'-';
         len += 1;
@@ -106,6 +436,21 @@ unsafe fn u64_buf(mut val: u64, buf: &mut [u8]) -> &[u8] {
     &buf[pos..]
 }
 
+/// 只使用32位运算, 除以常数10会被编译器优化为乘法
+#[inline(never)]
+fn u32_buf(mut val: u32, buf: &mut [u8; 24]) -> &[u8] {
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (val % 10) as u8;
+        val /= 10;
+        if val == 0 {
+            break;
+        }
+    }
+    &buf[pos..]
+}
+
 #[inline(never)]
 fn hex_buf(mut val: u64, buf: &mut [u8; 24]) -> &[u8] {
     let mut pos = buf.len();
@@ -125,159 +470,128 @@ fn hex_buf(mut val: u64, buf: &mut [u8; 24]) -> &[u8] {
     &buf[pos..]
 }
 
-/// f64::log10/powf依赖std,无法使用. 这里输出的是(+/-)(1/0).dddddd*2^(+/-)d,
-/// 是2的指数，而非10的指数
+/// `{:X}`, `{:o}`, `{:b}`只需要移位, 64位参数不经过`u128_buf`, 不会引入128位运算
 #[inline(never)]
-fn f64_buf(val: f64, buf: &mut [u8; 24]) -> &[u8] {
-    if val.is_nan() {
-        return b"nan";
+fn radix_buf(mut val: u64, ty: u8, buf: &mut [u8; 64]) -> &[u8] {
+    let shift = match ty {
+        b'X' => 4,
+        b'o' => 3,
+        _ => 1,
     };
-
-    if val.is_infinite() {
-        if val.is_sign_negative() {
-            return b"-inf";
+    let mask = (1 << shift) - 1;
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b"0123456789ABCDEF"[(val & mask) as usize];
+        val >>= shift;
+        if val == 0 {
+            break;
         }
-        return b"info";
     }
+    &buf[pos..]
+}
 
-    let (sign, denormal, fract, exp) = f64_decode(val);
-    let mut len = unsafe { u64_buf(exp.abs() as u64, buf).len() };
-    len += 1;
-    if exp < 0 {
-        buf[buf.len() - len] = b'-';
-    } else {
-        buf[buf.len() - len] = b'+';
-    }
-    for b in b"^2*" {
-        len += 1;
-        buf[buf.len() - len] = *b;
-    }
+/// 输出到buf, 同snprintf一样在末尾保留并写入'\0', 返回值为不截断时的完整长度.
+pub struct BufFormatter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    marker: &'static [u8],
+}
 
-    let end = buf.len() - len;
-    let d6 = (fract * 1_000_000.0) as u64;
-    let len6 = unsafe { u64_buf(d6, &mut buf[..end]).len() };
-    len += len6;
-    for _ in len6..6 {
-        len += 1;
-        buf[buf.len() - len] = b'0';
+impl<'a> BufFormatter<'a> {
+    /// 输出到buf
+    pub fn from_slice(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            marker: b"",
+        }
     }
 
-    len += 1;
-    buf[buf.len() - len] = b'.';
-    len += 1;
-    if denormal {
-        buf[buf.len() - len] = b'0';
-    } else {
-        buf[buf.len() - len] = b'1';
+    /// # Safety
+    /// 调用者保证buf指针有效，长度至少为len, 并且在返回值使用期间没有其他引用
+    pub unsafe fn new(buf: *mut u8, len: usize) -> Self {
+        Self::from_slice(core::slice::from_raw_parts_mut(buf, len))
     }
 
-    if sign {
-        len += 1;
-        buf[buf.len() - len] = b'-';
+    /// 已经写入的字节数, 不包括末尾的'\0'
+    pub fn pos(&self) -> usize {
+        self.pos
     }
 
-    &buf[buf.len() - len..]
-}
-
-// return (sign, denormal, fract, exp)
-fn f64_decode(val: f64) -> (bool, bool, f64, i64) {
-    let bits = val.to_bits();
-    let s = bits >> 63;
-    let e = (bits >> 52) & 0x7FF;
-    let mut m = bits & ((0x01 << 52) - 1);
-    let mut exp = e as i64 - 1023;
-    if e == 0 {
-        if m == 0 {
-            return (true, true, 0.0, 0);
-        }
-        let h = hi_bit_1(m) as i64;
-        exp = -(1022 + 52 - h);
-        m <<= 52 - h;
+    /// 还能写入的字节数, 不包括末尾的'\0'
+    pub fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(1) - self.pos
     }
 
-    let mut fract = 0.0f64;
-    let mut n = 0.5f64;
-    m <<= 12;
-    while m > 0 {
-        if (m & (0x01 << 63)) > 0 {
-            fract += n;
-        }
-        m <<= 1;
-        n /= 2.0;
+    /// 已经写入的内容, 不包括末尾的'\0'
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.pos]
     }
 
-    (s > 0, e == 0, fract, exp)
-}
-
-fn hi_bit_1(mut n: u64) -> u64 {
-    if n == 0 {
-        return 0;
-    }
-    let mut b = 1;
-    let mut m = n & 0xFFFF_FFFF_0000_0000;
-    if m > 0 {
-        n = m;
-        b += 32;
-    }
-    m = n & 0xFFFF_0000_FFFF_0000;
-    if m > 0 {
-        n = m;
-        b += 16;
-    }
-    m = n & 0xFF00_FF00_FF00_FF00;
-    if m > 0 {
-        n = m;
-        b += 8;
-    }
-    m = n & 0xF0F0_F0F0_F0F0_F0F0;
-    if m > 0 {
-        n = m;
-        b += 4;
-    }
-    m = n & 0xCCCC_CCCC_CCCC_CCCC;
-    if m > 0 {
-        n = m;
-        b += 2;
-    }
-    m = n & 0xAAAA_AAAA_AAAA_AAAA;
-    if m > 0 {
-        b += 1;
-    }
-    b
-}
-
-pub struct BufFormatter<'a> {
-    buf: &'a mut [u8],
-    pos: usize,
-}
+    /// 已经写入的内容, `{:rb}`或者截断可能导致不是合法的UTF-8
+    pub fn as_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
 
-impl BufFormatter<'_> {
-    /// # Safety
-    /// 调用者保证buf指针有效，长度至少为len
-    pub unsafe fn new(buf: *mut u8, len: usize) -> Self {
-        Self {
-            buf: core::slice::from_raw_parts_mut(buf, len),
-            pos: 0,
-        }
+    /// 输出被截断时在'\0'之前写入`marker`, 例如`b"..."`; buf放不下`marker`时不写入
+    pub fn with_marker(mut self, marker: &'static [u8]) -> Self {
+        self.marker = marker;
+        self
     }
 }
 
 impl Formatter for BufFormatter<'_> {
     fn write_buf(&mut self, buf: &[u8]) -> usize {
-        let len = buf.len().min(self.buf.len() - self.pos);
+        let cap = match self.buf.len().checked_sub(1) {
+            Some(cap) => cap,
+            None => return buf.len(),
+        };
+        let len = buf.len().min(cap - self.pos);
         self.buf[self.pos..self.pos + len].copy_from_slice(&buf[..len]);
         self.pos += len;
+        self.buf[self.pos] = 0;
+        if len < buf.len() && self.marker.len() <= cap {
+            self.buf[cap - self.marker.len()..cap].copy_from_slice(self.marker);
+        }
         buf.len()
     }
     fn new(_fd: i32) -> Self {
         Self {
             buf: &mut [],
             pos: 0,
+            marker: b"",
         }
     }
 }
 
+/// 追加到`Vec<u8>`末尾, 不写入'\0'. `format_bytes!`先计算长度, 再一次分配之后输出到这里.
+#[cfg(feature = "alloc")]
+impl Formatter for alloc::vec::Vec<u8> {
+    fn new(_fd: i32) -> Self {
+        Self::new()
+    }
+    fn write_buf(&mut self, buf: &[u8]) -> usize {
+        self.extend_from_slice(buf);
+        buf.len()
+    }
+}
+
+/// 追加到`String`末尾, `{:rb}`输出的非UTF-8字节替换为U+FFFD
+#[cfg(feature = "alloc")]
+impl Formatter for alloc::string::String {
+    fn new(_fd: i32) -> Self {
+        Self::new()
+    }
+    fn write_buf(&mut self, buf: &[u8]) -> usize {
+        self.push_str(&alloc::string::String::from_utf8_lossy(buf));
+        buf.len()
+    }
+}
+
+pub use hifmt_macros::nolibc_args as args;
 pub use hifmt_macros::nolibc_bprint as bprint;
+pub use hifmt_macros::nolibc_bprint_slice as bprint_slice;
 pub use hifmt_macros::nolibc_cbprint as cbprint;
 pub use hifmt_macros::nolibc_ceprint as ceprint;
 pub use hifmt_macros::nolibc_ceprintln as ceprintln;
@@ -286,14 +600,23 @@ pub use hifmt_macros::nolibc_cprintln as cprintln;
 pub use hifmt_macros::nolibc_csprint as csprint;
 pub use hifmt_macros::nolibc_eprint as eprint;
 pub use hifmt_macros::nolibc_eprintln as eprintln;
+pub use hifmt_macros::nolibc_format_array as format_array;
+#[cfg(feature = "alloc")]
+pub use hifmt_macros::nolibc_format_bytes as format_bytes;
+#[cfg(feature = "alloc")]
+pub use hifmt_macros::nolibc_format_string as format;
 pub use hifmt_macros::nolibc_print as print;
 pub use hifmt_macros::nolibc_println as println;
 pub use hifmt_macros::nolibc_sprint as sprint;
+pub use hifmt_macros::nolibc_sprint_str as sprint_str;
+pub use hifmt_macros::nolibc_write as write;
+pub use hifmt_macros::nolibc_writeln as writeln;
 
 #[cfg(test)]
 mod test {
     use super::*;
     extern crate std;
+    use super::float::f64_decode;
     use std::*;
 
     fn datas() -> &'static [f64] {
@@ -320,10 +643,13 @@ mod test {
         assert_eq!(sign, false);
         assert_eq!(denormal, false);
 
-        let mut buf = [0_u8; 24];
+        assert_eq!(f64_decode(0.0).0, false);
+        assert_eq!(f64_decode(-0.0).0, true);
+
         for f in datas() {
-            let s = f64_buf(*f, &mut buf);
-            let s = core::str::from_utf8(s).unwrap();
+            let mut buf = VecFormatter::new(1);
+            buf.write_f64(*f);
+            let s = core::str::from_utf8(&buf.0).unwrap();
             let (sign, denormal, fract, exp) = f64_decode(*f);
             let mut nf = if denormal { fract } else { 1.0 + fract };
             nf = nf * 2_f64.powf(exp as f64);
@@ -334,4 +660,503 @@ mod test {
             assert_eq!(*f, nf);
         }
     }
+
+    extern "C" {
+        fn snprintf(buf: *mut u8, size: usize, format: *const u8, ...) -> i32;
+    }
+
+    fn libc_f64(format: &str, val: f64) -> string::String {
+        let format = ffi::CString::new(format).unwrap();
+        let mut buf = [0_u8; 1024];
+        let len = unsafe { snprintf(buf.as_mut_ptr(), buf.len(), format.as_ptr().cast(), val) };
+        string::String::from_utf8(buf[..len as usize].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_f64_libc() {
+        let specs = [
+            ("%e", Spec::DEFAULT),
+            (
+                "%.0e",
+                Spec {
+                    precision: Some(0),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%#.0e",
+                Spec {
+                    precision: Some(0),
+                    alternate: true,
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%+.12e",
+                Spec {
+                    precision: Some(12),
+                    sign: true,
+                    ..Spec::DEFAULT
+                },
+            ),
+            // 超过f64精确展开长度(767位)的精度, 数字逐位生成不受缓冲区大小限制
+            (
+                "%.800e",
+                Spec {
+                    precision: Some(800),
+                    ..Spec::DEFAULT
+                },
+            ),
+            ("%f", Spec::DEFAULT),
+            (
+                "%.330f",
+                Spec {
+                    precision: Some(330),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%.0f",
+                Spec {
+                    precision: Some(0),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%.3f",
+                Spec {
+                    precision: Some(3),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%020.4f",
+                Spec {
+                    precision: Some(4),
+                    zero: true,
+                    width: 20,
+                    ..Spec::DEFAULT
+                },
+            ),
+            ("%g", Spec::DEFAULT),
+            (
+                "%.0g",
+                Spec {
+                    precision: Some(0),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%.17g",
+                Spec {
+                    precision: Some(17),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%#g",
+                Spec {
+                    alternate: true,
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%-12g",
+                Spec {
+                    width: 12,
+                    align: Align::Left,
+                    ..Spec::DEFAULT
+                },
+            ),
+            ("%a", Spec::DEFAULT),
+            (
+                "%.0a",
+                Spec {
+                    precision: Some(0),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%#.0a",
+                Spec {
+                    precision: Some(0),
+                    alternate: true,
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%.2a",
+                Spec {
+                    precision: Some(2),
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%+.15a",
+                Spec {
+                    precision: Some(15),
+                    sign: true,
+                    ..Spec::DEFAULT
+                },
+            ),
+            (
+                "%024a",
+                Spec {
+                    zero: true,
+                    width: 24,
+                    ..Spec::DEFAULT
+                },
+            ),
+        ];
+        let mut vals = vec::Vec::from(datas());
+        vals.extend_from_slice(&[
+            0.0,
+            0.5,
+            1.5,
+            2.5,
+            0.125,
+            9.9999996,
+            999999.4,
+            1e-5,
+            0.00012345,
+            123456789.0,
+            1e21,
+            5e-324,
+            2.2250738585072014e-308,
+            f64::EPSILON,
+            1.0 + 3.0 * f64::EPSILON,
+            1.99951171875,
+            1.00048828125,
+            1.00146484375,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ]);
+        for (format, spec) in specs {
+            for val in &vals {
+                let mut f = VecFormatter::new(1);
+                let size = match format.as_bytes()[format.len() - 1] {
+                    b'a' => f.write_f64_hex(*val, &spec),
+                    b'e' => f.write_f64_exp(*val, &spec),
+                    b'f' => f.write_f64_fixed(*val, &spec),
+                    _ => f.write_f64_general(*val, &spec),
+                };
+                let s = str::from_utf8(&f.0).unwrap();
+                assert_eq!(s, libc_f64(format, *val), "{format} {val:e}");
+                assert_eq!(size, f.0.len());
+            }
+        }
+
+        // glibc在进位后丢失了`#`要求保留的0, 这里遵循C标准
+        let mut f = VecFormatter::new(1);
+        f.write_f64_general(
+            999999.5,
+            &Spec {
+                alternate: true,
+                ..Spec::DEFAULT
+            },
+        );
+        assert_eq!(f.0, b"1.00000e+06");
+    }
+
+    #[test]
+    fn test_f32_libc() {
+        let spec = Spec {
+            precision: Some(9),
+            ..Spec::DEFAULT
+        };
+        let vals = [
+            0.1f32,
+            -3.5,
+            1e-40,
+            f32::MIN_POSITIVE,
+            f32::MAX,
+            f32::NAN,
+            -f32::INFINITY,
+        ];
+        for val in vals {
+            let mut f = VecFormatter::new(1);
+            f.write_f32(val);
+            f.write_buf(b" ");
+            f.write_f32_fixed(val, &spec);
+            f.write_buf(b" ");
+            f.write_f32_general(val, &spec);
+            f.write_buf(b" ");
+            f.write_f32_hex(val, &Spec::DEFAULT);
+            let s = str::from_utf8(&f.0).unwrap();
+            let val = val as f64;
+            let expect = ["%e", "%.9f", "%.9g", "%a"].map(|format| libc_f64(format, val));
+            assert_eq!(s, expect.join(" "));
+        }
+    }
+
+    struct VecFormatter(vec::Vec<u8>);
+
+    impl Formatter for VecFormatter {
+        fn new(_: i32) -> Self {
+            VecFormatter(vec::Vec::new())
+        }
+        fn write_buf(&mut self, buf: &[u8]) -> usize {
+            self.0.extend_from_slice(buf);
+            buf.len()
+        }
+    }
+
+    #[test]
+    fn test_i32() {
+        let specs = [
+            Spec::DEFAULT,
+            Spec {
+                sign: true,
+                zero: true,
+                width: 8,
+                ..Spec::DEFAULT
+            },
+            Spec {
+                precision: Some(4),
+                align: Align::Left,
+                width: 6,
+                ..Spec::DEFAULT
+            },
+        ];
+        for val in [0, 7, -7, 10, 99999, i32::MAX, i32::MIN, -1_000_000_000] {
+            let (mut f32, mut f64) = (VecFormatter::new(1), VecFormatter::new(1));
+            assert_eq!(f32.write_i32(val), f64.write_i64(val as i64));
+            assert_eq!(f32.write_u32(val as u32), f64.write_u64(val as u32 as u64));
+            for spec in &specs {
+                assert_eq!(
+                    f32.write_i32_spec(val, spec),
+                    f64.write_i64_spec(val as i64, spec)
+                );
+                let val = val as u32;
+                assert_eq!(
+                    f32.write_u32_spec(val, spec),
+                    f64.write_u64_spec(val as u64, spec)
+                );
+            }
+            assert_eq!(f32.0, f64.0);
+        }
+    }
+
+    #[test]
+    fn test_radix() {
+        let alt = Spec {
+            alternate: true,
+            ..Spec::DEFAULT
+        };
+        let mut f = VecFormatter::new(1);
+        f.write_oct(0o755);
+        f.write_upper_hex(0xBEEF);
+        f.write_bin(u64::MAX);
+        f.write_oct_spec(8, &alt);
+        f.write_bin_spec(0, &alt);
+        f.write_upper_hex_spec(255, &spec('0', Align::Right, 6, None));
+        f.write_bin_spec(
+            5,
+            &Spec {
+                zero: true,
+                width: 8,
+                ..alt
+            },
+        );
+        f.write_oct_spec(7, &spec(' ', Align::Left, 5, Some(3)));
+        f.write_oct(u64::MAX);
+        f.write_upper_hex_spec(u64::MAX, &alt);
+        assert_eq!(
+            f.0,
+            b"755BEEF1111111111111111111111111111111111111111111111111111111111111111\
+                           0o1000000FF0b000101007  17777777777777777777770xFFFFFFFFFFFFFFFF"
+        );
+    }
+
+    #[test]
+    fn test_i128() {
+        let spec = Spec {
+            sign: true,
+            zero: true,
+            width: 8,
+            ..Spec::DEFAULT
+        };
+        for val in [
+            7,
+            -7,
+            99999,
+            i64::MIN as i128,
+            u64::MAX as i128 + 1,
+            10_i128.pow(19),
+        ] {
+            let (mut f, mut expect) = (VecFormatter::new(1), VecFormatter::new(1));
+            assert_eq!(
+                f.write_i128(val),
+                expect.write_buf(std::format!("{}", val).as_bytes())
+            );
+            assert_eq!(
+                f.write_u128(val as u128),
+                expect.write_buf(std::format!("{}", val as u128).as_bytes())
+            );
+            assert_eq!(
+                f.write_hex128_spec(
+                    val as u128,
+                    &Spec {
+                        alternate: true,
+                        ..spec
+                    }
+                ),
+                expect.write_buf(std::format!("{:#08x}", val as u128).as_bytes())
+            );
+            assert_eq!(
+                f.write_i128_spec(val, &spec),
+                expect.write_buf(std::format!("{:+08}", val).as_bytes())
+            );
+            assert_eq!(f.0, expect.0);
+        }
+        let mut f = VecFormatter::new(1);
+        f.write_u128(0);
+        f.write_i128(i128::MIN);
+        f.write_u128_spec(u128::MAX, &Spec::DEFAULT);
+        assert_eq!(
+            f.0,
+            b"0-170141183460469231731687303715884105728340282366920938463463374607431768211455"
+        );
+    }
+
+    fn spec(fill: char, align: Align, width: usize, precision: Option<usize>) -> Spec {
+        Spec {
+            fill,
+            align,
+            sign: false,
+            alternate: false,
+            zero: false,
+            width,
+            precision,
+        }
+    }
+
+    #[test]
+    fn test_buf_formatter() {
+        let mut buf = [b'#'; 8];
+        let mut f = BufFormatter::from_slice(&mut buf);
+        assert_eq!((f.pos(), f.remaining()), (0, 7));
+        assert_eq!(f.write_buf(b"abc"), 3);
+        assert_eq!((f.pos(), f.remaining()), (3, 4));
+        assert_eq!((f.as_bytes(), f.as_str()), (&b"abc"[..], Ok("abc")));
+        assert_eq!(&buf, b"abc\0####");
+
+        let mut f = BufFormatter::from_slice(&mut buf);
+        assert_eq!(f.write_buf(b"hello"), 5);
+        assert_eq!(f.write_buf(b" world"), 6);
+        assert_eq!((f.pos(), f.remaining()), (7, 0));
+        assert_eq!(&buf, b"hello w\0");
+
+        let mut f = BufFormatter::from_slice(&mut buf).with_marker(b"...");
+        assert_eq!(f.write_buf(b"hello"), 5);
+        assert_eq!(f.as_bytes(), b"hello");
+        assert_eq!(f.write_buf(b" world"), 6);
+        assert_eq!(f.write_buf(b"!"), 1);
+        assert_eq!(&buf, b"hell...\0");
+
+        let mut f = BufFormatter::from_slice(&mut buf[..3]).with_marker(b"...");
+        assert_eq!(f.write_buf(b"hello"), 5);
+        assert_eq!(&buf[..3], b"he\0");
+
+        let mut f = BufFormatter::from_slice(&mut buf[..3]);
+        f.write_buf("中".as_bytes());
+        assert!(f.as_str().is_err());
+
+        let mut f = BufFormatter::from_slice(&mut []);
+        assert_eq!(f.write_buf(b"hello"), 5);
+        assert_eq!((f.pos(), f.remaining(), f.as_bytes()), (0, 0, &b""[..]));
+
+        let mut f = unsafe { BufFormatter::new(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(f.write_buf(b"raw"), 3);
+        assert_eq!(&buf[..4], b"raw\0");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_alloc_formatter() {
+        let mut v = vec::Vec::new();
+        assert_eq!(v.write_buf(b"a\0"), 2);
+        assert_eq!(v.write_buf(b"b"), 1);
+        assert_eq!(v, b"a\0b");
+
+        let mut s = string::String::new();
+        assert_eq!(s.write_buf("中".as_bytes()), 3);
+        assert_eq!(s.write_buf(b"\xff!"), 2);
+        assert_eq!(s, "中\u{fffd}!");
+    }
+
+    #[test]
+    fn test_spec() {
+        let mut f = VecFormatter::new(1);
+        let right = spec(' ', Align::Right, 6, None);
+        let size = f.write_i64_spec(-42, &right)
+            + f.write_i64_spec(
+                42,
+                &Spec {
+                    sign: true,
+                    ..right
+                },
+            )
+            + f.write_i64_spec(
+                -42,
+                &Spec {
+                    zero: true,
+                    ..right
+                },
+            )
+            + f.write_u64_spec(5, &spec(' ', Align::Right, 6, Some(3)))
+            + f.write_u64_spec(0, &spec(' ', Align::Right, 0, Some(0)))
+            + f.write_hex_spec(
+                0xff,
+                &Spec {
+                    alternate: true,
+                    zero: true,
+                    ..right
+                },
+            )
+            + f.write_hex_spec(
+                0,
+                &Spec {
+                    alternate: true,
+                    ..right
+                },
+            )
+            + f.write_buf_spec(b"abcdef", &spec('*', Align::Center, 6, Some(3)))
+            + f.write_buf_spec(b"ab", &spec('.', Align::Left, 4, None))
+            + f.write_buf_spec("中".as_bytes(), &spec('中', Align::Right, 4, None))
+            + unsafe { f.write_cstr_spec(b"hello".as_ptr(), &spec(' ', Align::Right, 0, Some(2))) }
+            + f.write_u64_spec(7, &right.with_width(-3))
+            + f.write_u64_spec(7, &right.with_precision(-1));
+        let s = str::from_utf8(&f.0).unwrap();
+        assert_eq!(
+            s,
+            "   -42   +42-00042   0050x00ff     0*abc**ab..中中he7       7"
+        );
+        assert_eq!(size, f.0.len());
+    }
+
+    #[test]
+    fn test_pad() {
+        // 记录每次`write_buf`的长度, 填充按32字节分批输出
+        struct Writes(vec::Vec<usize>, vec::Vec<u8>);
+        impl Formatter for Writes {
+            fn new(_: i32) -> Self {
+                Writes(vec::Vec::new(), vec::Vec::new())
+            }
+            fn write_buf(&mut self, buf: &[u8]) -> usize {
+                self.0.push(buf.len());
+                self.1.extend_from_slice(buf);
+                buf.len()
+            }
+        }
+        let mut f = Writes::new(1);
+        assert_eq!(f.write_pad('0', 70), 70);
+        assert_eq!(f.0, [32, 32, 6]);
+        assert_eq!(f.write_pad('中', 12), 36);
+        assert_eq!(f.0[3..], [30, 6]);
+        assert_eq!(f.write_pad(' ', 0), 0);
+        assert_eq!(f.0.len(), 5);
+        assert_eq!(f.1, ["0".repeat(70), "中".repeat(12)].concat().as_bytes());
+    }
 }
@@ -0,0 +1,655 @@
+//! 浮点数输出. `no-float`特性只在宏中拒绝浮点格式, 这里的实现没有使用时由链接器丢弃
+
+use super::{hex_buf, padding, u64_buf, Formatter, Spec};
+
+/// 浮点数的输出风格, 对应printf的`%e`/`%f`/`%g`/`%a`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Style {
+    Exp,
+    Fixed,
+    General,
+    Hex,
+}
+
+/// f64需要的大整数limb个数: 整数部分< 2^1024, 小数部分乘以10之后< 2^1078, 10^9进制的整数部分最多35段
+pub(super) const F64_LIMBS: usize = 36;
+/// f32需要的limb个数: 整数部分< 2^128, 小数部分乘以10之后< 2^153
+pub(super) const F32_LIMBS: usize = 6;
+
+/// 舍入位置: 保留的有效数字个数, 或者保留的小数位数
+enum Round {
+    Sig(usize),
+    Frac(usize),
+}
+
+/// 小端u32数组表示的无符号大整数, 容量N由浮点类型决定, 见`F64_LIMBS`和`F32_LIMBS`
+struct Big<const N: usize> {
+    limbs: [u32; N],
+    len: usize,
+}
+
+impl<const N: usize> Big<N> {
+    fn new(val: u64) -> Self {
+        let mut big = Big {
+            limbs: [0; N],
+            len: 2,
+        };
+        big.limbs[0] = val as u32;
+        big.limbs[1] = (val >> 32) as u32;
+        big.trim();
+        big
+    }
+
+    fn trim(&mut self) {
+        while self.len > 0 && self.limbs[self.len - 1] == 0 {
+            self.len -= 1;
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.len == 0
+    }
+
+    fn shl(&mut self, n: usize) {
+        if self.is_zero() {
+            return;
+        }
+        let (words, bits) = (n / 32, (n % 32) as u32);
+        self.limbs[self.len + words] = 0;
+        for i in (0..self.len).rev() {
+            let v = self.limbs[i];
+            if bits > 0 {
+                self.limbs[i + words + 1] |= v >> (32 - bits);
+            }
+            self.limbs[i + words] = v << bits;
+        }
+        self.limbs[..words].fill(0);
+        self.len += words + 1;
+        self.trim();
+    }
+
+    fn mul_small(&mut self, k: u32) {
+        let mut carry = 0_u64;
+        for limb in &mut self.limbs[..self.len] {
+            let v = *limb as u64 * k as u64 + carry;
+            *limb = v as u32;
+            carry = v >> 32;
+        }
+        if carry > 0 {
+            self.limbs[self.len] = carry as u32;
+            self.len += 1;
+        }
+    }
+
+    /// 除以k, 返回余数
+    fn div_small(&mut self, k: u32) -> u32 {
+        let mut rem = 0_u64;
+        for limb in self.limbs[..self.len].iter_mut().rev() {
+            let v = (rem << 32) | *limb as u64;
+            *limb = (v / k as u64) as u32;
+            rem = v % k as u64;
+        }
+        self.trim();
+        rem as u32
+    }
+
+    /// 返回`self >> bit`(调用者保证小于2^32), self只保留低bit位
+    fn split_at(&mut self, bit: usize) -> u32 {
+        let (word, bits) = (bit / 32, (bit % 32) as u32);
+        if word >= self.len {
+            return 0;
+        }
+        let mut high = self.limbs[word] >> bits;
+        if bits > 0 && word + 1 < self.len {
+            high |= self.limbs[word + 1] << (32 - bits);
+        }
+        self.limbs[word] &= (1_u32 << bits) - 1;
+        self.len = word + 1;
+        self.trim();
+        high
+    }
+}
+
+/// 精确的逐位十进制展开: 整数部分预先转为10^9进制, 小数部分每次乘10取出整数位
+struct DigitGen<const N: usize> {
+    chunks: [u32; N],
+    chunk: usize,
+    div: u32,
+    first: Option<u8>,
+    frac: Big<N>,
+    shift: usize,
+}
+
+impl<const N: usize> DigitGen<N> {
+    /// 展开m*2^e(m > 0), 同时返回最高位数字所在的10的幂次
+    fn new(m: u64, e: i32) -> (Self, i32) {
+        let (mut int, frac, shift): (Big<N>, _, _) = if e >= 0 {
+            let mut int = Big::new(m);
+            int.shl(e as usize);
+            (int, Big::new(0), 0)
+        } else {
+            let shift = e.unsigned_abs() as usize;
+            if shift < 64 {
+                (
+                    Big::new(m >> shift),
+                    Big::new(m & ((1 << shift) - 1)),
+                    shift,
+                )
+            } else {
+                (Big::new(0), Big::new(m), shift)
+            }
+        };
+        let mut gen = DigitGen {
+            chunks: [0; N],
+            chunk: 0,
+            div: 1,
+            first: None,
+            frac,
+            shift,
+        };
+        while !int.is_zero() {
+            gen.chunks[gen.chunk] = int.div_small(1_000_000_000);
+            gen.chunk += 1;
+        }
+        if gen.chunk > 0 {
+            let top = gen.chunks[gen.chunk - 1];
+            let mut lead = 9 * (gen.chunk as i32 - 1);
+            while gen.div <= top / 10 {
+                gen.div *= 10;
+                lead += 1;
+            }
+            return (gen, lead);
+        }
+        let mut lead = -1;
+        loop {
+            gen.frac.mul_small(10);
+            let d = gen.frac.split_at(shift) as u8;
+            if d != 0 {
+                gen.first = Some(d);
+                return (gen, lead);
+            }
+            lead -= 1;
+        }
+    }
+
+    fn next(&mut self) -> u8 {
+        if let Some(d) = self.first.take() {
+            return d;
+        }
+        if self.chunk > 0 {
+            let d = self.chunks[self.chunk - 1] / self.div % 10;
+            if self.div == 1 {
+                self.chunk -= 1;
+                self.div = 100_000_000;
+            } else {
+                self.div /= 10;
+            }
+            return d as u8;
+        }
+        if self.frac.is_zero() {
+            return 0;
+        }
+        self.frac.mul_small(10);
+        self.frac.split_at(self.shift) as u8
+    }
+
+    /// 之后输出的数字是否全部为0
+    fn is_done(&self) -> bool {
+        self.first.is_none() && !self.rest_nonzero()
+    }
+
+    /// 尚未输出的数字中是否还有非0数字
+    fn rest_nonzero(&self) -> bool {
+        if !self.frac.is_zero() {
+            return true;
+        }
+        if self.chunk == 0 {
+            return false;
+        }
+        let cur = self.chunks[self.chunk - 1] as u64 % (self.div as u64 * 10);
+        cur != 0 || self.chunks[..self.chunk - 1].iter().any(|c| *c != 0)
+    }
+}
+
+/// 舍入之后的数字
+enum Digits<const N: usize> {
+    Zero,
+    /// 10^exp位上为1, 其余为0: 舍入进位到更高一位, 或者所有数字都在舍入位置之后
+    One,
+    /// 由`gen`逐位展开, `next`为下一个数字的幂次, 低于`stop`的位为0.
+    /// `up`为舍入进位时加1的位置, 更低的位均为9, 进位后为0
+    Gen {
+        gen: DigitGen<N>,
+        next: i32,
+        stop: i32,
+        up: Option<i32>,
+    },
+}
+
+/// 舍入后的十进制数, 不保存数字串, 输出时从高到低逐位展开.
+/// `exp`为最高位的幂次, `last`为最后一个非0数字的幂次(数值为0时为None)
+struct Decimal<const N: usize> {
+    digits: Digits<N>,
+    exp: i32,
+    last: Option<i32>,
+}
+
+impl<const N: usize> Decimal<N> {
+    /// 10^pos位上的数字, 调用时pos必须递减
+    fn digit(&mut self, pos: i32) -> u8 {
+        match &mut self.digits {
+            Digits::Zero => 0,
+            Digits::One => (pos == self.exp) as u8,
+            Digits::Gen {
+                gen,
+                next,
+                stop,
+                up,
+            } => {
+                if pos > self.exp || pos < *stop {
+                    return 0;
+                }
+                while *next > pos {
+                    gen.next();
+                    *next -= 1;
+                }
+                let d = gen.next();
+                *next -= 1;
+                match *up {
+                    Some(up) if pos == up => d + 1,
+                    Some(up) if pos < up => 0,
+                    _ => d,
+                }
+            }
+        }
+    }
+}
+
+/// 将m*2^e精确舍入(同glibc一样四舍六入五成双)到指定位置.
+/// 第一遍展开找到进位的位置和最后一个非0数字, 输出时重新展开, 不需要保存全部数字
+#[inline(never)]
+fn decimal<const N: usize>(m: u64, e: i32, round: Round) -> Decimal<N> {
+    let zero = Decimal {
+        digits: Digits::Zero,
+        exp: 0,
+        last: None,
+    };
+    if m == 0 {
+        return zero;
+    }
+    let (mut gen, lead) = DigitGen::<N>::new(m, e);
+    let stop = match round {
+        Round::Sig(n) => lead - n as i32 + 1,
+        Round::Frac(n) => -(n as i32),
+    };
+    let one = |exp| Decimal {
+        digits: Digits::One,
+        exp,
+        last: Some(exp),
+    };
+    let count = lead - stop + 1;
+    if count <= 0 {
+        // 所有数字都位于舍入位置之后, 结果为0或者舍入位置上的1
+        if count == 0 {
+            let d = gen.next();
+            if d > 5 || (d == 5 && gen.rest_nonzero()) {
+                return one(stop);
+            }
+        }
+        return zero;
+    }
+
+    let (mut not_nine, mut nonzero) = (None, None);
+    let (mut pos, mut d) = (lead, 0);
+    while pos >= stop {
+        if gen.is_done() {
+            // 剩余的数字都是0
+            (not_nine, d) = (Some(stop), 0);
+            break;
+        }
+        d = gen.next();
+        if d != 9 {
+            not_nine = Some(pos);
+        }
+        if d != 0 {
+            nonzero = Some(pos);
+        }
+        pos -= 1;
+    }
+    let next = gen.next();
+    let up = next > 5 || (next == 5 && (d & 1 == 1 || gen.rest_nonzero()));
+    if up && not_nine.is_none() {
+        return one(lead + 1);
+    }
+    let (gen, _) = DigitGen::<N>::new(m, e);
+    Decimal {
+        digits: Digits::Gen {
+            gen,
+            next: lead,
+            stop,
+            up: if up { not_nine } else { None },
+        },
+        exp: lead,
+        last: if up { not_nine } else { nonzero },
+    }
+}
+
+/// 输出从10^from开始往下的count位数字, 在栈上凑满一小段再调用`write_buf`
+fn write_digits<F: Formatter + ?Sized, const N: usize>(
+    f: &mut F,
+    dec: &mut Decimal<N>,
+    from: i32,
+    count: usize,
+) -> usize {
+    let mut buf = [0_u8; 32];
+    let (mut n, mut size, mut pos) = (0, 0, from);
+    for _ in 0..count {
+        buf[n] = b'0' + dec.digit(pos);
+        pos -= 1;
+        n += 1;
+        if n == buf.len() {
+            size += f.write_buf(&buf);
+            n = 0;
+        }
+    }
+    if n > 0 {
+        size += f.write_buf(&buf[..n]);
+    }
+    size
+}
+
+/// `Formatter::write_f64_***`的实现
+pub(super) fn write_f64<F: Formatter + ?Sized>(
+    f: &mut F,
+    val: f64,
+    style: Style,
+    spec: &Spec,
+) -> usize {
+    if !val.is_finite() {
+        return write_nonfinite(f, val.is_sign_negative(), val.is_nan(), spec);
+    }
+    let (neg, m, e) = f64_parts(val);
+    write_float::<_, F64_LIMBS>(f, neg, m, e, style, spec)
+}
+
+/// `Formatter::write_f32_***`的实现, 只使用整数运算, 不会引入双精度浮点运算库
+pub(super) fn write_f32<F: Formatter + ?Sized>(
+    f: &mut F,
+    val: f32,
+    style: Style,
+    spec: &Spec,
+) -> usize {
+    let bits = val.to_bits();
+    let neg = bits >> 31 > 0;
+    let e = ((bits >> 23) & 0xFF) as i32;
+    let m = (bits & ((1 << 23) - 1)) as u64;
+    match e {
+        0xFF => write_nonfinite(f, neg, m != 0, spec),
+        0 => write_float::<_, F32_LIMBS>(f, neg, m, -149, style, spec),
+        _ => write_float::<_, F32_LIMBS>(f, neg, m | (1 << 23), e - 150, style, spec),
+    }
+}
+
+fn write_float<F: Formatter + ?Sized, const N: usize>(
+    f: &mut F,
+    neg: bool,
+    m: u64,
+    e: i32,
+    style: Style,
+    spec: &Spec,
+) -> usize {
+    match style {
+        Style::Hex => write_hex(f, neg, m, e, spec),
+        _ => write_decimal::<F, N>(f, neg, m, e, style, spec),
+    }
+}
+
+/// 按printf的`%e`/`%f`/`%g`规则输出m*2^e, 负数由neg标识
+#[inline(never)]
+fn write_decimal<F: Formatter + ?Sized, const N: usize>(
+    f: &mut F,
+    neg: bool,
+    m: u64,
+    e: i32,
+    style: Style,
+    spec: &Spec,
+) -> usize {
+    let precision = spec.precision.unwrap_or(6);
+    let (mut dec, exp_style, frac) = match style {
+        Style::Exp => (
+            decimal::<N>(m, e, Round::Sig(precision + 1)),
+            true,
+            precision,
+        ),
+        Style::Fixed => (decimal::<N>(m, e, Round::Frac(precision)), false, precision),
+        _ => {
+            let p = precision.max(1);
+            let dec = decimal::<N>(m, e, Round::Sig(p));
+            let x = dec.exp;
+            let exp_style = x < -4 || x >= p as i32;
+            let mut frac = if exp_style {
+                p - 1
+            } else {
+                (p as i32 - 1 - x) as usize
+            };
+            if !spec.alternate {
+                let sig = dec.last.map_or(0, |last| x - last + 1);
+                let need = if exp_style { sig - 1 } else { sig - 1 - x };
+                frac = frac.min(need.max(0) as usize);
+            }
+            (dec, exp_style, frac)
+        }
+    };
+
+    let mut exp_buf = [0_u8; 24];
+    let exp_digits = unsafe { u64_buf(dec.exp.unsigned_abs() as u64, &mut exp_buf) };
+    let point = frac > 0 || spec.alternate;
+    let int_len = if exp_style || dec.exp < 0 {
+        1
+    } else {
+        dec.exp as usize + 1
+    };
+    let mut len = int_len + point as usize + frac;
+    if exp_style {
+        len += 2 + exp_digits.len().max(2);
+    }
+    let sign: &[u8] = if neg {
+        b"-"
+    } else if spec.sign {
+        b"+"
+    } else {
+        b""
+    };
+    let (left, zeros, right) = padding(sign.len() + len, spec);
+
+    let mut size = f.write_pad(spec.fill, left);
+    size += f.write_buf(sign);
+    size += f.write_pad('0', zeros);
+    let top = if exp_style {
+        dec.exp
+    } else {
+        int_len as i32 - 1
+    };
+    size += write_digits(f, &mut dec, top, int_len);
+    if point {
+        size += f.write_buf(b".");
+    }
+    size += write_digits(f, &mut dec, top - int_len as i32, frac);
+    if exp_style {
+        size += f.write_buf(if dec.exp < 0 { b"e-" } else { b"e+" });
+        if exp_digits.len() < 2 {
+            size += f.write_buf(b"0");
+        }
+        size += f.write_buf(exp_digits);
+    }
+    size + f.write_pad(spec.fill, right)
+}
+
+/// 按printf的`%a`规则输出m*2^e: 0x1.hhhhp+d, 与glibc一致f64的非规格化数输出为0x0.hhhhp-1022,
+/// 指定精度时舍入后首位可能进位为2
+#[inline(never)]
+fn write_hex<F: Formatter + ?Sized>(f: &mut F, neg: bool, m: u64, e: i32, spec: &Spec) -> usize {
+    let bits = 64 - m.leading_zeros() as i32;
+    let (mut lead, mut frac, exp) = if m == 0 {
+        (0_u8, 0, 0)
+    } else if e + bits - 1 < -1022 {
+        let shift = e + 1074;
+        let frac = if shift < 0 { m >> -shift } else { m << shift };
+        (0, frac, -1022)
+    } else {
+        (1, (m << (53 - bits)) & ((1 << 52) - 1), e + bits - 1)
+    };
+    let mut digits = 13;
+    match spec.precision {
+        Some(p) if p < 13 => {
+            let shift = 4 * (13 - p);
+            let rem = frac & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            frac >>= shift;
+            let odd = if p == 0 { lead & 1 == 1 } else { frac & 1 == 1 };
+            if rem > half || (rem == half && odd) {
+                frac += 1;
+                if frac >> (4 * p) == 1 {
+                    frac = 0;
+                    lead += 1;
+                }
+            }
+            digits = p;
+        }
+        Some(_) => {}
+        None => {
+            while digits > 0 && frac & 0xF == 0 {
+                frac >>= 4;
+                digits -= 1;
+            }
+        }
+    }
+    let extra = spec.precision.map_or(0, |p| p.saturating_sub(13));
+
+    let mut frac_buf = [0_u8; 24];
+    let frac_digits: &[u8] = if digits > 0 {
+        hex_buf(frac, &mut frac_buf)
+    } else {
+        b""
+    };
+    let mut exp_buf = [0_u8; 24];
+    let exp_digits = unsafe { u64_buf(exp.unsigned_abs() as u64, &mut exp_buf) };
+    let sign: &[u8] = match (neg, spec.sign) {
+        (true, _) => b"-0x",
+        (false, true) => b"+0x",
+        (false, false) => b"0x",
+    };
+    let point = digits + extra > 0 || spec.alternate;
+    let len = sign.len() + 1 + point as usize + digits + extra + 2 + exp_digits.len();
+    let (left, zeros, right) = padding(len, spec);
+
+    let mut size = f.write_pad(spec.fill, left);
+    size += f.write_buf(sign);
+    size += f.write_pad('0', zeros);
+    size += f.write_buf(&[b'0' + lead]);
+    if point {
+        size += f.write_buf(b".");
+    }
+    size += f.write_pad('0', digits - frac_digits.len());
+    size += f.write_buf(frac_digits);
+    size += f.write_pad('0', extra);
+    size += f.write_buf(if exp < 0 { b"p-" } else { b"p+" });
+    size += f.write_buf(exp_digits);
+    size + f.write_pad(spec.fill, right)
+}
+
+/// 输出nan/inf, 同glibc一样负数NaN输出为`-nan`, 忽略`0`标志
+fn write_nonfinite<F: Formatter + ?Sized>(f: &mut F, neg: bool, nan: bool, spec: &Spec) -> usize {
+    let sign: &[u8] = if neg {
+        b"-"
+    } else if spec.sign {
+        b"+"
+    } else {
+        b""
+    };
+    let body: &[u8] = if nan { b"nan" } else { b"inf" };
+    let spec = Spec {
+        zero: false,
+        ..*spec
+    };
+    f.write_field(sign, 0, body, &spec)
+}
+
+/// 拆分为(sign, m, e), 数值为m*2^e. fract最多52位有效二进制位, 乘以2^52是精确的
+fn f64_parts(val: f64) -> (bool, u64, i32) {
+    let (sign, denormal, fract, exp) = f64_decode(val);
+    let m = (fract * (1_u64 << 52) as f64) as u64;
+    let e = exp as i32 - 52;
+    if denormal {
+        (sign, m, e)
+    } else {
+        (sign, m | (1 << 52), e)
+    }
+}
+
+// return (sign, denormal, fract, exp)
+pub(super) fn f64_decode(val: f64) -> (bool, bool, f64, i64) {
+    let bits = val.to_bits();
+    let s = bits >> 63;
+    let e = (bits >> 52) & 0x7FF;
+    let mut m = bits & ((0x01 << 52) - 1);
+    let mut exp = e as i64 - 1023;
+    if e == 0 {
+        if m == 0 {
+            return (s > 0, true, 0.0, 0);
+        }
+        let h = hi_bit_1(m) as i64;
+        exp = -(1022 + 52 - h);
+        m <<= 52 - h;
+    }
+
+    let mut fract = 0.0f64;
+    let mut n = 0.5f64;
+    m <<= 12;
+    while m > 0 {
+        if (m & (0x01 << 63)) > 0 {
+            fract += n;
+        }
+        m <<= 1;
+        n /= 2.0;
+    }
+
+    (s > 0, e == 0, fract, exp)
+}
+
+fn hi_bit_1(mut n: u64) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let mut b = 1;
+    let mut m = n & 0xFFFF_FFFF_0000_0000;
+    if m > 0 {
+        n = m;
+        b += 32;
+    }
+    m = n & 0xFFFF_0000_FFFF_0000;
+    if m > 0 {
+        n = m;
+        b += 16;
+    }
+    m = n & 0xFF00_FF00_FF00_FF00;
+    if m > 0 {
+        n = m;
+        b += 8;
+    }
+    m = n & 0xF0F0_F0F0_F0F0_F0F0;
+    if m > 0 {
+        n = m;
+        b += 4;
+    }
+    m = n & 0xCCCC_CCCC_CCCC_CCCC;
+    if m > 0 {
+        n = m;
+        b += 2;
+    }
+    m = n & 0xAAAA_AAAA_AAAA_AAAA;
+    if m > 0 {
+        b += 1;
+    }
+    b
+}